@@ -0,0 +1,66 @@
+// The pocket command, checking the settings of a service before deploying
+// it:
+//
+//     pocket check [--settings-file PATH] [--profile NAME] [--config-file PATH] [--config-dir PATH]
+//
+// Environment variables are checked as well, the same way the service
+// loads them. The values of the typed `[config]` section are not, only the
+// service knows their types (see `Check::with_config`).
+
+use pocket::check::Check;
+
+const USAGE: &str = "usage: pocket check [--settings-file PATH] [--profile NAME] [--config-file PATH] [--config-dir PATH]";
+
+const HELP: &str = "
+Checks the settings file of a service, its profile and the environment
+variables the same way the service loads them, reporting every problem.
+
+The typed [config] section is only loaded, its values are not checked: the
+service can check them with pocket::check::Check::with_config.";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("check") => {}
+        Some("--help" | "-h" | "help") => return print_help(),
+        _ => exit_with_usage(),
+    }
+
+    let mut check = Check::default();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return print_help();
+        }
+
+        let value = match args.next() {
+            Some(value) => value,
+            None => exit_with_usage(),
+        };
+
+        match arg.as_str() {
+            "--settings-file" => check.with_settings_file(value),
+            "--profile" => check.with_profile(&value),
+            "--config-file" => check.with_config_file(value),
+            "--config-dir" => check.with_config_dir(value),
+            _ => exit_with_usage(),
+        };
+    }
+
+    let report = check.run();
+    if !report.is_ok() {
+        eprint!("{}", report);
+        std::process::exit(1);
+    }
+
+    println!("ok");
+}
+
+fn print_help() {
+    println!("{}\n{}", USAGE, HELP);
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
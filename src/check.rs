@@ -0,0 +1,577 @@
+// Validation of everything a service is configured by, the settings file,
+// its profile, environment variables and typed settings, reporting all
+// problems at once, with their files and keys. Used by the `pocket check`
+// command, and by services checking their own typed settings.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use figment::{Figment, Metadata, Source};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::config::{self, vars, ConfigBuilder};
+use crate::definition::{self, validation, ServiceDefinition, ServiceKind};
+use crate::flags::{self, Flags};
+use crate::grpc::tls::{TlsOptions, TlsSettings};
+use crate::grpc::web::GrpcWebSettings;
+use crate::http::settings::HttpSettings;
+use crate::service::listener::{self, BindAddress};
+
+/// Top level keys of the settings file.
+const KNOWN_KEYS: [&str; 12] = [
+    "name", "version", "type", "server", "database", "logging", "clients", "pubsub", "http",
+    "grpc", "flags", "config",
+];
+
+/// A problem found, with the file and the key it was found at, when known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub file: Option<PathBuf>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+/// The problems found by a check.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+/// Gives back the settings file a key was set at.
+type Locate<'a> = dyn Fn(&str) -> Option<PathBuf> + 'a;
+
+type ConfigCheck = fn(&Figment, &Locate) -> Vec<Problem>;
+type SectionCheck = fn(&toml::Value) -> Option<String>;
+
+/// Checks the settings of a service, the same way `ServiceBuilder` loads
+/// them, including the environment variables.
+///
+/// ```ignore
+/// let report = Check::default().with_profile("prod").with_config::<Settings>().run();
+/// if !report.is_ok() {
+///     eprintln!("{}", report);
+///     std::process::exit(1);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Check {
+    settings_file: Option<PathBuf>,
+    profile: Option<String>,
+    config_file: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+    config_checks: Vec<ConfigCheck>,
+}
+
+impl Check {
+    pub fn with_settings_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.settings_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_profile(&mut self, profile: &str) -> &mut Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    pub fn with_config_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_config_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Checks the typed settings of the service too, as `T`.
+    pub fn with_config<T: DeserializeOwned + Validate>(&mut self) -> &mut Self {
+        self.config_checks.push(config_problems::<T>);
+        self
+    }
+
+    pub fn run(&self) -> Report {
+        let mut problems = env_problems();
+
+        let path = env_or(definition::SETTINGS_FILE_ENV, self.settings_file.clone());
+        let path = match path {
+            Some(path) => path,
+            None => match ServiceDefinition::get_settings_file_path() {
+                Ok(path) => path,
+                Err(e) => return Report::new(problems, vec![problem(None, None, e)]),
+            },
+        };
+
+        let mut content = match parse_file(&path) {
+            Ok(content) => content,
+            Err(p) => return Report::new(problems, vec![p]),
+        };
+
+        // Keys are located in the profile file when it sets them.
+        let mut overlay = None;
+        let profile: Option<String> = env_or(definition::PROFILE_ENV, self.profile.clone());
+        if let Some(profile) = profile.filter(|p| !p.is_empty()) {
+            if !definition::is_valid_profile(&profile) {
+                problems.push(problem(
+                    None,
+                    Some("profile"),
+                    format!("'{}' must have only letters, digits, '-' or '_'", profile),
+                ));
+            } else {
+                let file = ServiceDefinition::profile_file_path(&path, &profile);
+                match parse_file(&file) {
+                    Ok(value) => {
+                        definition::merge_values(&mut content, value.clone());
+                        overlay = Some((file, value));
+                    }
+                    Err(p) => problems.push(p),
+                }
+            }
+        }
+
+        let locate = |key: &str| -> Option<PathBuf> {
+            match &overlay {
+                Some((file, value)) if has_key(value, key) => Some(file.clone()),
+                _ => Some(path.clone()),
+            }
+        };
+
+        if let Some(table) = content.as_table() {
+            for key in table.keys() {
+                if !KNOWN_KEYS.contains(&key.as_str()) {
+                    problems.push(problem(locate(key), Some(key), "unknown setting"));
+                }
+            }
+        }
+
+        // Sections that can not be parsed are reported and left out, so that
+        // the other ones are still checked.
+        let mut parsed = content.clone();
+        let definition: Option<ServiceDefinition> = match content.clone().try_into() {
+            Ok(definition) => Some(definition),
+            Err(e) => {
+                let found = section_problems(&content);
+                if found.is_empty() {
+                    problems.push(problem(Some(path.clone()), None, e));
+                }
+
+                for (key, message) in found {
+                    if key.is_empty() {
+                        problems.push(problem(Some(path.clone()), None, message));
+                    } else {
+                        problems.push(problem(locate(&key), Some(&key), message));
+                        if let Some(table) = parsed.as_table_mut() {
+                            table.remove(&key);
+                        }
+                    }
+                }
+
+                parsed.clone().try_into().ok()
+            }
+        };
+
+        let found = match &definition {
+            Some(definition) => definition.problems(),
+            None => section_validation(&parsed),
+        };
+
+        for (key, message) in found {
+            problems.push(problem(locate(&key), Some(&key), message));
+        }
+
+        let server: definition::ServerDefinition = section(&parsed, "server");
+        let bind = listener::port(None, &server)
+            .and_then(|port| BindAddress::from_settings(None, port, None, &server));
+        if let Err(e) = bind {
+            problems.push(problem(locate("server"), Some("server"), e));
+        }
+
+        let kind = parsed
+            .get("type")
+            .and_then(toml::Value::as_str)
+            .map(ServiceKind::from_str);
+        if kind == Some(ServiceKind::Http) {
+            if let Err(e) = HttpSettings::new(&section(&parsed, "http")) {
+                problems.push(problem(locate("http"), Some("http"), e));
+            }
+        }
+
+        let grpc: definition::GrpcDefinition = section(&parsed, "grpc");
+        if let Err(e) = GrpcWebSettings::new(None, &grpc) {
+            problems.push(problem(locate("grpc"), Some("grpc"), e));
+        }
+
+        if let Err(e) = TlsSettings::new(&TlsOptions::default(), &grpc) {
+            problems.push(problem(locate("grpc.tls"), Some("grpc.tls"), e));
+        }
+
+        // Invalid environment variables are reported on their own, the flags
+        // of the settings files are checked without them.
+        let definitions: HashMap<String, definition::FlagDefinition> = section(&parsed, "flags");
+        let flag_problems = flags::env_problems(&definitions);
+        if flag_problems.is_empty() {
+            if let Err(e) = Flags::new(&definitions) {
+                problems.push(problem(locate("flags"), Some("flags"), e));
+            }
+        }
+
+        for (name, message) in flag_problems {
+            problems.push(problem(None, Some(&name), message));
+        }
+
+        let config_file = env_or(config::CONFIG_FILE_ENV, self.config_file.clone());
+        let config_dir = env_or(config::CONFIG_DIR_ENV, self.config_dir.clone());
+        let config = ConfigBuilder::new()
+            .with_section(parsed.get("config"))
+            .with_dir(config_dir.as_deref())
+            .with_file(config_file.as_deref())
+            .build();
+
+        match config {
+            Ok(config) => {
+                let figment = config.figment();
+                for check in &self.config_checks {
+                    problems.extend(check(&figment, &locate));
+                }
+            }
+            Err(e) => problems.push(problem(None, Some("config"), e)),
+        }
+
+        Report { problems }
+    }
+}
+
+impl Report {
+    fn new(mut problems: Vec<Problem>, more: Vec<Problem>) -> Self {
+        problems.extend(more);
+        Report { problems }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the environment variables that are silently ignored by the
+/// service when they can not be parsed.
+fn env_problems() -> Vec<Problem> {
+    vars::problems()
+        .into_iter()
+        .map(|(name, message)| problem(None, Some(name), message))
+        .collect()
+}
+
+/// Deserializes every section on its own, so that all of them are
+/// reported. Problems with the service info are given back without a key.
+fn section_problems(content: &toml::Value) -> Vec<(String, String)> {
+    fn check<T: DeserializeOwned>(value: &toml::Value) -> Option<String> {
+        value.clone().try_into::<T>().err().map(|e| e.to_string())
+    }
+
+    let sections: [(&str, SectionCheck); 9] = [
+        ("server", check::<definition::ServerDefinition>),
+        ("database", check::<definition::DatabaseDefinition>),
+        ("logging", check::<definition::LoggingDefinition>),
+        (
+            "clients",
+            check::<HashMap<String, definition::ClientDefinition>>,
+        ),
+        ("pubsub", check::<definition::PubsubDefinition>),
+        ("http", check::<definition::HttpDefinition>),
+        ("grpc", check::<definition::GrpcDefinition>),
+        (
+            "flags",
+            check::<HashMap<String, definition::FlagDefinition>>,
+        ),
+        ("config", check::<toml::Value>),
+    ];
+
+    let mut problems = Vec::new();
+    if let Some(message) = check::<definition::ServiceInfo>(content) {
+        problems.push((String::new(), message));
+    }
+
+    for (key, check) in sections {
+        if let Some(message) = content.get(key).and_then(check) {
+            problems.push((key.to_string(), message));
+        }
+    }
+
+    problems
+}
+
+/// Validates the sections that can be parsed on their own, for settings
+/// files whose service info can not be.
+fn section_validation(content: &toml::Value) -> Vec<(String, String)> {
+    fn check<T: DeserializeOwned + Validate>(
+        content: &toml::Value,
+        key: &str,
+    ) -> Vec<(String, String)> {
+        let value: Option<T> = content.get(key).and_then(|v| v.clone().try_into().ok());
+        match value.map(|v| v.validate()) {
+            Some(Err(e)) => validation::problems(key, &e),
+            _ => Vec::new(),
+        }
+    }
+
+    let mut problems = Vec::new();
+    problems.extend(check::<definition::ServerDefinition>(content, "server"));
+    problems.extend(check::<definition::DatabaseDefinition>(content, "database"));
+    problems.extend(check::<definition::LoggingDefinition>(content, "logging"));
+    problems.extend(check::<definition::PubsubDefinition>(content, "pubsub"));
+
+    let clients: HashMap<String, definition::ClientDefinition> = section(content, "clients");
+    let mut names: Vec<&String> = clients.keys().collect();
+    names.sort();
+    for name in names {
+        if let Err(e) = clients[name].validate() {
+            problems.extend(validation::problems(&format!("clients.{}", name), &e));
+        }
+    }
+
+    problems
+}
+
+/// Gives back a section of the settings, or its default when it is not set
+/// or can not be parsed.
+fn section<T: DeserializeOwned + Default>(content: &toml::Value, key: &str) -> T {
+    content
+        .get(key)
+        .and_then(|v| v.clone().try_into().ok())
+        .unwrap_or_default()
+}
+
+fn config_problems<T: DeserializeOwned + Validate>(
+    figment: &Figment,
+    locate: &Locate,
+) -> Vec<Problem> {
+    let value: T = match figment.extract() {
+        Ok(value) => value,
+        Err(e) => {
+            return e
+                .into_iter()
+                .map(|e| {
+                    let key = std::iter::once("config".to_string())
+                        .chain(e.path.iter().cloned())
+                        .collect::<Vec<_>>()
+                        .join(".");
+
+                    let metadata = e.metadata.as_ref();
+                    let file = config_file(metadata, &key, locate);
+
+                    let mut message = e.kind.to_string();
+                    if let (None, Some(metadata)) = (&file, metadata) {
+                        message = format!("{} (from {})", message, metadata.name);
+                    }
+
+                    problem(file, Some(&key), message)
+                })
+                .collect();
+        }
+    };
+
+    match value.validate() {
+        Ok(()) => Vec::new(),
+        Err(e) => validation::problems("config", &e)
+            .into_iter()
+            .map(|(key, message)| {
+                // Figment finds values by their dotted path, without the
+                // index of list items.
+                let path = key.trim_start_matches("config.");
+                let path = path.split('[').next().unwrap_or(path);
+                let file = config_file(figment.find_metadata(path), &key, locate);
+
+                problem(file, Some(&key), message)
+            })
+            .collect(),
+    }
+}
+
+/// Gives back the file a key of the typed settings was set at: its own file,
+/// or the settings file for the `[config]` section. Keys set by environment
+/// variables have none.
+fn config_file(metadata: Option<&Metadata>, key: &str, locate: &Locate) -> Option<PathBuf> {
+    match metadata.and_then(|m| m.source.as_ref()) {
+        Some(Source::File(path)) => Some(path.clone()),
+        Some(Source::Code(_)) => locate(key),
+        _ => None,
+    }
+}
+
+fn parse_file(path: &Path) -> Result<toml::Value, Problem> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| problem(Some(path.to_path_buf()), None, e))?;
+
+    toml::from_str(&content).map_err(|e| problem(Some(path.to_path_buf()), None, e))
+}
+
+/// Tells if a dotted key, like `http.cors`, is set in a value.
+fn has_key(value: &toml::Value, key: &str) -> bool {
+    key.split('.')
+        .try_fold(value, |value, name| value.get(name))
+        .is_some()
+}
+
+fn env_or<T: FromStr>(key: &str, default: Option<T>) -> Option<T> {
+    use crate::config::{Config, GetEnv};
+    Config::get_os_env(key, default)
+}
+
+fn problem<M: ToString>(file: Option<PathBuf>, key: Option<&str>, message: M) -> Problem {
+    Problem {
+        file,
+        key: key.map(|k| k.to_string()),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde_derive::Deserialize, Validate)]
+    struct Settings {
+        #[validate(range(min = 1, max = 100))]
+        page_size: u32,
+    }
+
+    #[test]
+    pub fn test_check() {
+        let _env = crate::testing::EnvGuard::new();
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let path = dir.join("service.toml");
+        std::fs::write(
+            &path,
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "grpc"
+
+            [server]
+            port = 8080
+
+            [config]
+            page_size = 10
+        "#,
+        )
+        .unwrap();
+
+        std::fs::write(
+            dir.join("service.prod.toml"),
+            r#"
+            type = "consumer"
+            extra = true
+
+            [server]
+            port = 70000
+
+            [clients.payments]
+            address = "payments"
+
+            [config]
+            page_size = 0
+        "#,
+        )
+        .unwrap();
+
+        let report = Check::default()
+            .with_settings_file(&path)
+            .with_config::<Settings>()
+            .run();
+        assert!(report.is_ok(), "{}", report);
+
+        let report = Check::default()
+            .with_settings_file(&path)
+            .with_profile("prod")
+            .with_config::<Settings>()
+            .run();
+
+        let prod = Some(dir.join("service.prod.toml"));
+        let found: Vec<(Option<PathBuf>, Option<String>)> = report
+            .problems
+            .iter()
+            .map(|p| (p.file.clone(), p.key.clone()))
+            .collect();
+
+        for key in ["extra", "type", "server.port", "clients.payments.address"] {
+            assert!(
+                found.contains(&(prod.clone(), Some(key.to_string()))),
+                "{} not in {}",
+                key,
+                report
+            );
+        }
+        assert!(found.contains(&(prod.clone(), Some("config.page_size".to_string()))));
+
+        std::fs::write(
+            dir.join("service.prod.toml"),
+            "[server]\nport = \"eighty\"\n[database]\nport = \"27017\"\n\
+             [clients.payments]\naddress = \"payments\"\n",
+        )
+        .unwrap();
+
+        let report = Check::default()
+            .with_settings_file(&path)
+            .with_profile("prod")
+            .with_config::<Settings>()
+            .run();
+        let keys: Vec<Option<&str>> = report.problems.iter().map(|p| p.key.as_deref()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                Some("server"),
+                Some("database"),
+                Some("clients.payments.address")
+            ]
+        );
+
+        // Sections are still validated when the service info is invalid.
+        std::fs::write(
+            dir.join("service.prod.toml"),
+            "version = 1\n[clients.payments]\naddress = \"payments\"\n[config]\npage_size = 0\n",
+        )
+        .unwrap();
+
+        let report = Check::default()
+            .with_settings_file(&path)
+            .with_profile("prod")
+            .with_config::<Settings>()
+            .run();
+        let keys: Vec<Option<&str>> = report.problems.iter().map(|p| p.key.as_deref()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                None,
+                Some("clients.payments.address"),
+                Some("config.page_size")
+            ]
+        );
+    }
+}
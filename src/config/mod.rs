@@ -0,0 +1,399 @@
+// Service configuration, from environment variables, secrets and typed
+// settings loaded by layers: the `[config]` section of the settings file, an
+// optional directory of files, an optional overlay file and SERVICE_ prefixed
+// environment variables. Typed settings can be reloaded while the service
+// runs.
+
+pub mod reload;
+pub mod secrets;
+pub(crate) mod vars;
+
+use std::any::TypeId;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::Figment;
+use logger::{fields::FieldValue, Logger};
+use serde::de::DeserializeOwned;
+use tokio::sync::watch;
+use validator::Validate;
+
+use crate::config::reload::ConfigWatch;
+use crate::config::secrets::{Secret, SecretProvider, Secrets};
+use crate::error::{Error, Result};
+
+/// Prefix of the environment variables overriding the typed settings. Nested
+/// keys are separated by `__`, like `SERVICE_DATABASE__TIMEOUT`.
+pub const ENV_PREFIX: &str = "SERVICE_";
+
+/// Environment variable with the path of the overlay file.
+pub const CONFIG_FILE_ENV: &str = "SERVICE_CONFIG_FILE";
+
+/// Environment variable with the path of a directory of `.toml` files.
+pub const CONFIG_DIR_ENV: &str = "SERVICE_CONFIG_DIR";
+
+/// SERVICE_ prefixed environment variables used by pocket itself, which are
+/// not part of the typed settings.
+const RESERVED_ENV: [&str; 8] = [
+    "port",
+    "address",
+    "timeout",
+    "dual_stack",
+    "config_file",
+    "config_dir",
+    "settings_file",
+    "profile",
+];
+
+type Validator = fn(&Figment) -> Result<()>;
+
+#[derive(Debug)]
+pub struct Config {
+    logger: Option<Arc<Logger>>,
+    dir: Option<PathBuf>,
+    file: Option<PathBuf>,
+    sender: watch::Sender<Arc<Figment>>,
+    receiver: watch::Receiver<Arc<Figment>>,
+    validators: Mutex<Vec<(TypeId, Validator)>>,
+    secrets: Arc<Secrets>,
+}
+
+pub(crate) struct ConfigBuilder {
+    logger: Option<Arc<Logger>>,
+    section: Option<toml::Value>,
+    dir: Option<PathBuf>,
+    file: Option<PathBuf>,
+    secret_providers: Vec<Arc<dyn SecretProvider>>,
+}
+
+pub(crate) trait GetEnv<T> {
+    fn get_os_env(key: &str, default: Option<T>) -> Option<T>;
+}
+
+impl Config {
+    fn new(builder: &ConfigBuilder) -> Result<Self> {
+        let figment = Config::layers(
+            builder.section.as_ref(),
+            builder.dir.as_deref(),
+            builder.file.as_deref(),
+        )?;
+
+        let (sender, receiver) = watch::channel(Arc::new(figment));
+
+        Ok(Config {
+            logger: builder.logger.as_ref().cloned(),
+            dir: builder.dir.clone(),
+            file: builder.file.clone(),
+            sender,
+            receiver,
+            validators: Mutex::new(Vec::new()),
+            secrets: Arc::new(Secrets::new(&builder.secret_providers)),
+        })
+    }
+
+    fn layers(
+        section: Option<&toml::Value>,
+        dir: Option<&Path>,
+        file: Option<&Path>,
+    ) -> Result<Figment> {
+        let mut figment = Figment::new();
+
+        if let Some(section) = section {
+            figment = figment.merge(Serialized::defaults(section));
+        }
+
+        if let Some(dir) = dir {
+            for file in reload::dir_files(dir)? {
+                figment = figment.merge(Toml::file(file));
+            }
+        }
+
+        if let Some(file) = file {
+            if !file.is_file() {
+                return Err(Error::Config(format!(
+                    "file '{}' does not exist",
+                    file.display()
+                )));
+            }
+
+            figment = figment.merge(Toml::file(file));
+        }
+
+        figment = figment.merge(Env::prefixed(ENV_PREFIX).ignore(&RESERVED_ENV).split("__"));
+
+        // Syntax errors of the files are reported here, when the service
+        // starts or reloads, instead of when the settings are loaded.
+        if let Err(e) = figment.extract::<figment::value::Dict>() {
+            return Err(config_error(e));
+        }
+
+        Ok(figment)
+    }
+
+    /// Rebuilds the typed settings with a new `[config]` section, publishing
+    /// them to the watchers when they changed. Updates that do not pass the
+    /// validation of every watched type are rejected, keeping the current
+    /// settings.
+    pub(crate) fn reload(&self, section: Option<&toml::Value>) -> Result<bool> {
+        let figment = Config::layers(section, self.dir.as_deref(), self.file.as_deref())?;
+
+        for (_, validate) in self.validators.lock().unwrap().iter() {
+            validate(&figment)?;
+        }
+
+        let current = self.receiver.borrow().extract::<figment::value::Dict>();
+        if figment.extract::<figment::value::Dict>().ok() == current.ok() {
+            return Ok(false);
+        }
+
+        // The config keeps a receiver, so sending never fails.
+        let _ = self.sender.send(Arc::new(figment));
+        Ok(true)
+    }
+
+    /// Files and directories the typed settings are loaded from.
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        self.dir.iter().chain(self.file.iter()).cloned().collect()
+    }
+
+    /// The current layers of the typed settings.
+    pub(crate) fn figment(&self) -> Arc<Figment> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Gives access to the service secrets, to register rotation callbacks
+    /// or to refresh them.
+    pub fn secrets(&self) -> &Arc<Secrets> {
+        &self.secrets
+    }
+
+    /// Gives back the secret of a reference, like `file:///run/secrets/db`,
+    /// `env:DB_PASSWORD` or `vault:secret/data/db#password`.
+    pub async fn secret(&self, reference: &str) -> Result<Secret> {
+        self.secrets.get(reference).await
+    }
+
+    /// Loads the typed settings of the service and validates them.
+    ///
+    /// ```ignore
+    /// #[derive(Deserialize, Validate)]
+    /// struct Settings {
+    ///     #[validate(range(min = 1))]
+    ///     page_size: u32,
+    /// }
+    ///
+    /// let settings: Settings = service.config.load()?;
+    /// ```
+    pub fn load<T: DeserializeOwned + Validate>(&self) -> Result<T> {
+        extract(&self.receiver.borrow())
+    }
+
+    /// Gives back a handle to the typed settings, which is notified when
+    /// they are reloaded. From now on, reloads are only accepted when they
+    /// are valid for `T` too.
+    ///
+    /// ```ignore
+    /// let mut settings = service.config.watch::<Settings>()?;
+    /// tokio::spawn(async move {
+    ///     while let Ok(settings) = settings.changed().await {
+    ///         apply(settings);
+    ///     }
+    /// });
+    /// ```
+    pub fn watch<T: DeserializeOwned + Validate + 'static>(&self) -> Result<ConfigWatch<T>> {
+        let validate: Validator = |figment| extract::<T>(figment).map(|_| ());
+        validate(&self.receiver.borrow())?;
+
+        let mut validators = self.validators.lock().unwrap();
+        if !validators.iter().any(|(id, _)| *id == TypeId::of::<T>()) {
+            validators.push((TypeId::of::<T>(), validate));
+        }
+
+        Ok(ConfigWatch::new(self.receiver.clone()))
+    }
+
+    pub fn get_env(&self, key: &str, default_value: &str) -> Option<String> {
+        match std::env::var(key.to_string()) {
+            Err(_) => {
+                if !default_value.is_empty() {
+                    Some(default_value.to_string())
+                } else {
+                    None
+                }
+            }
+            Ok(value) => Some(value),
+        }
+    }
+
+    pub fn must_get_env(&self, key: &str) -> Option<String> {
+        let k = key.to_string();
+        match std::env::var(&k) {
+            Err(_) => {
+                if let Some(log) = &self.logger {
+                    log.debugf(
+                        "environment variable must be set",
+                        logger::fields!("name" => FieldValue::String(k)),
+                    );
+                }
+
+                None
+            }
+            Ok(v) => Some(v),
+        }
+    }
+}
+
+impl<T: std::str::FromStr> GetEnv<T> for Config {
+    fn get_os_env(key: &str, default: Option<T>) -> Option<T> {
+        match std::env::var(key) {
+            Err(_) => default,
+            Ok(value) => match value.parse::<T>() {
+                Err(_) => default,
+                Ok(v) => Some(v),
+            },
+        }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder {
+            logger: None,
+            section: None,
+            dir: None,
+            file: None,
+            secret_providers: Vec::new(),
+        }
+    }
+
+    pub fn with_logger(&mut self, logger: &Arc<Logger>) -> &mut Self {
+        self.logger = Some(logger.clone());
+        self
+    }
+
+    /// Sets the `[config]` section of the settings file, the lowest layer
+    /// of the typed settings.
+    pub fn with_section(&mut self, section: Option<&toml::Value>) -> &mut Self {
+        self.section = section.cloned();
+        self
+    }
+
+    /// Sets a directory whose `.toml` files, in name order, overlay the
+    /// `[config]` section.
+    pub fn with_dir(&mut self, dir: Option<&Path>) -> &mut Self {
+        self.dir = dir.map(|d| d.to_path_buf());
+        self
+    }
+
+    /// Sets a file overlaying the `[config]` section and the directory. It
+    /// must exist.
+    pub fn with_file(&mut self, file: Option<&Path>) -> &mut Self {
+        self.file = file.map(|f| f.to_path_buf());
+        self
+    }
+
+    pub fn with_secret_providers(&mut self, providers: &[Arc<dyn SecretProvider>]) -> &mut Self {
+        self.secret_providers = providers.to_vec();
+        self
+    }
+
+    pub fn build(&self) -> Result<Config> {
+        Config::new(self)
+    }
+}
+
+fn extract<T: DeserializeOwned + Validate>(figment: &Figment) -> Result<T> {
+    let value: T = figment.extract().map_err(config_error)?;
+
+    if let Err(e) = value.validate() {
+        return Err(Error::Config(e.to_string()));
+    }
+
+    Ok(value)
+}
+
+fn config_error(error: figment::Error) -> Error {
+    let messages: Vec<String> = error.into_iter().map(|e| e.to_string()).collect();
+    Error::Config(messages.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EnvGuard;
+    use logger::builder::LoggerBuilder;
+
+    #[test]
+    pub fn test_config_new() {
+        let log = Arc::new(LoggerBuilder::default().build());
+        let config = ConfigBuilder::new().with_logger(&log).build().unwrap();
+        config.must_get_env("TEST");
+        let config2 = ConfigBuilder::new().with_logger(&log).build().unwrap();
+        config2.must_get_env("TEST2");
+        let config3 = ConfigBuilder::new().build().unwrap();
+        config3.must_get_env("TEST3");
+        log.info("Test");
+    }
+
+    #[derive(Debug, serde_derive::Deserialize, Validate)]
+    struct Settings {
+        name: String,
+        #[validate(range(min = 1, max = 100))]
+        page_size: u32,
+        limits: Limits,
+    }
+
+    #[derive(Debug, serde_derive::Deserialize, Validate)]
+    struct Limits {
+        items: u32,
+        timeout: Option<u64>,
+    }
+
+    #[test]
+    pub fn test_config_load() {
+        let section: toml::Value = toml::from_str(
+            r#"
+            name = "example"
+            page_size = 10
+
+            [limits]
+            items = 5
+        "#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.toml");
+        std::fs::write(&file, "[limits]\nitems = 50\n").unwrap();
+        let mut env = EnvGuard::new();
+        env.set("SERVICE_LIMITS__TIMEOUT", "30");
+
+        let config = ConfigBuilder::new()
+            .with_section(Some(&section))
+            .with_file(Some(&file))
+            .build()
+            .unwrap();
+
+        let settings: Settings = config.load().unwrap();
+        assert_eq!(settings.name, "example");
+        assert_eq!(settings.page_size, 10);
+        assert_eq!(settings.limits.items, 50);
+        assert_eq!(settings.limits.timeout, Some(30));
+        drop(env);
+
+        std::fs::write(&file, "page_size = 0\n").unwrap();
+        let config = ConfigBuilder::new()
+            .with_section(Some(&section))
+            .with_file(Some(&file))
+            .build()
+            .unwrap();
+        assert!(config.load::<Settings>().is_err());
+
+        std::fs::write(&file, "page_size = \n").unwrap();
+        assert!(ConfigBuilder::new().with_file(Some(&file)).build().is_err());
+
+        std::fs::remove_file(&file).unwrap();
+        assert!(ConfigBuilder::new().with_file(Some(&file)).build().is_err());
+    }
+}
@@ -0,0 +1,163 @@
+// Reloading of the typed settings: handles notified about updates, and the
+// polling of the files the settings come from.
+
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use figment::Figment;
+use serde::de::DeserializeOwned;
+use tokio::sync::watch;
+use validator::Validate;
+
+use crate::error::{Error, Result};
+
+/// A handle to the typed settings of the service, given by
+/// [`Config::watch`](crate::config::Config::watch).
+#[derive(Debug)]
+pub struct ConfigWatch<T> {
+    receiver: watch::Receiver<Arc<Figment>>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned + Validate> ConfigWatch<T> {
+    pub(crate) fn new(receiver: watch::Receiver<Arc<Figment>>) -> Self {
+        ConfigWatch {
+            receiver,
+            marker: PhantomData,
+        }
+    }
+
+    /// Gives back the current settings.
+    pub fn current(&self) -> Result<T> {
+        super::extract(&self.receiver.borrow())
+    }
+
+    /// Waits until the settings are reloaded, giving back the new ones. It
+    /// fails when the service is gone.
+    pub async fn changed(&mut self) -> Result<T> {
+        if self.receiver.changed().await.is_err() {
+            return Err(Error::Config("service config is gone".to_string()));
+        }
+
+        self.current()
+    }
+}
+
+/// Tells when watched files change, by their modification time and size.
+/// Directories are watched by their `.toml` files.
+#[derive(Debug)]
+pub(crate) struct FileWatcher {
+    paths: Vec<PathBuf>,
+    state: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let state = snapshot(&paths);
+        FileWatcher { paths, state }
+    }
+
+    /// Tells if some file changed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let state = snapshot(&self.paths);
+        if state == self.state {
+            return false;
+        }
+
+        self.state = state;
+        true
+    }
+}
+
+fn snapshot(paths: &[PathBuf]) -> Vec<(PathBuf, Option<(SystemTime, u64)>)> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            files.extend(dir_files(path).unwrap_or_default());
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|file| {
+            let state = std::fs::metadata(&file)
+                .and_then(|m| Ok((m.modified()?, m.len())))
+                .ok();
+
+            (file, state)
+        })
+        .collect()
+}
+
+/// Gives back the `.toml` files of a directory, in name order.
+pub(crate) fn dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Config(format!("directory '{}': {}", dir.display(), e)))?;
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().map(|e| e == "toml").unwrap_or(false))
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    #[derive(Debug, serde_derive::Deserialize, Validate)]
+    struct Settings {
+        #[validate(range(min = 1))]
+        page_size: u32,
+        level: Option<String>,
+    }
+
+    #[tokio::test]
+    pub async fn test_config_reload() {
+        let _env = crate::testing::EnvGuard::new();
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("10-level.toml"), "level = \"info\"\n").unwrap();
+
+        let section: toml::Value = toml::from_str("page_size = 10").unwrap();
+        let config = ConfigBuilder::new()
+            .with_section(Some(&section))
+            .with_dir(Some(dir))
+            .build()
+            .unwrap();
+
+        let mut watch = config.watch::<Settings>().unwrap();
+        assert_eq!(watch.current().unwrap().level.as_deref(), Some("info"));
+
+        let mut watcher = FileWatcher::new(config.files());
+        assert!(!watcher.changed());
+        assert!(!config.reload(Some(&section)).unwrap());
+
+        let section: toml::Value = toml::from_str("page_size = 20").unwrap();
+        assert!(config.reload(Some(&section)).unwrap());
+        assert_eq!(watch.changed().await.unwrap().page_size, 20);
+
+        let invalid: toml::Value = toml::from_str("page_size = 0").unwrap();
+        assert!(config.reload(Some(&invalid)).is_err());
+        assert_eq!(config.load::<Settings>().unwrap().page_size, 20);
+
+        std::fs::write(dir.join("20-level.toml"), "level = \"debug\"\n").unwrap();
+        assert!(watcher.changed());
+        assert!(config.reload(Some(&section)).unwrap());
+        assert_eq!(
+            watch.changed().await.unwrap().level.as_deref(),
+            Some("debug")
+        );
+
+        std::fs::write(dir.join("20-level.toml"), "level = \n").unwrap();
+        assert!(config.reload(Some(&section)).is_err());
+    }
+}
@@ -0,0 +1,749 @@
+// Secrets, resolved from references like `file:///run/secrets/db`,
+// `env:DB_PASSWORD` or `vault:secret/data/db#password` by providers
+// registered for their schemes. Settings refer to them with a `secret:`
+// prefix. Values are cached until refreshed, and never shown by Debug or
+// Display.
+
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::error::{Error, Result};
+
+/// Prefix of the settings values that are secret references, like
+/// `secret:file:///run/secrets/db`. Other values are used as they are.
+pub const REFERENCE_PREFIX: &str = "secret:";
+
+/// A secret value, redacted when formatted.
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+
+    /// Gives access to the secret value.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// Resolves the references of a scheme, receiving them without the scheme,
+/// like `/run/secrets/db` for `file:///run/secrets/db`.
+#[tonic::async_trait]
+pub trait SecretProvider: Send + Sync {
+    fn scheme(&self) -> &str;
+    async fn fetch(&self, path: &str) -> Result<String>;
+}
+
+/// Reads secrets from files, like the ones mounted by docker or kubernetes.
+/// Trailing line breaks are removed.
+#[derive(Debug, Default)]
+pub struct FileProvider;
+
+#[tonic::async_trait]
+impl SecretProvider for FileProvider {
+    fn scheme(&self) -> &str {
+        "file"
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(value) => Ok(value.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => Err(secret_error(path, &e.to_string())),
+        }
+    }
+}
+
+/// Reads secrets from environment variables.
+#[derive(Debug, Default)]
+pub struct EnvProvider;
+
+#[tonic::async_trait]
+impl SecretProvider for EnvProvider {
+    fn scheme(&self) -> &str {
+        "env"
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String> {
+        std::env::var(path).map_err(|_| secret_error(path, "variable is not set"))
+    }
+}
+
+/// A local stand-in for Vault, reading secrets from a JSON file with an
+/// object of keys for every path:
+///
+/// ```json
+/// { "secret/data/db": { "password": "example" } }
+/// ```
+///
+/// The file is read at every fetch, so that changes are seen by refreshes.
+#[derive(Debug)]
+pub struct LocalVault {
+    file: PathBuf,
+}
+
+impl LocalVault {
+    pub fn new<P: AsRef<Path>>(file: P) -> Self {
+        LocalVault {
+            file: file.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SecretProvider for LocalVault {
+    fn scheme(&self) -> &str {
+        "vault"
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String> {
+        let (path, key) = vault_path(path)?;
+        let content = tokio::fs::read(&self.file)
+            .await
+            .map_err(|e| secret_error(path, &e.to_string()))?;
+        let data: Value =
+            serde_json::from_slice(&content).map_err(|e| secret_error(path, &e.to_string()))?;
+
+        vault_value(&data[path], path, key)
+    }
+}
+
+/// Reads secrets from the HTTP API of a Vault server (or of a Vault agent),
+/// supporting both versions of the KV secrets engine. Servers reached with
+/// an https:// address are verified with the web PKI roots, or with the CA
+/// given by `with_ca`.
+pub struct VaultProvider {
+    address: hyper::Uri,
+    token: Secret,
+    tls: Option<TlsConnector>,
+}
+
+impl VaultProvider {
+    pub fn new(address: &str, token: &str) -> Result<Self> {
+        let uri: hyper::Uri = address
+            .trim_end_matches('/')
+            .parse()
+            .map_err(|_| Error::Secret(format!("vault address '{}' is invalid", address)))?;
+
+        let tls = match (uri.scheme_str(), uri.host()) {
+            (Some("http"), Some(_)) => None,
+            (Some("https"), Some(_)) => {
+                let mut roots = RootCertStore::empty();
+                roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                }));
+
+                Some(tls_connector(roots))
+            }
+            _ => {
+                return Err(Error::Secret(format!(
+                    "vault address '{}' must be an http:// or https:// URL",
+                    address
+                )))
+            }
+        };
+
+        Ok(VaultProvider {
+            address: uri,
+            token: Secret::new(token),
+            tls,
+        })
+    }
+
+    /// Verifies the server with the certificates of a PEM file content
+    /// instead of the web PKI roots.
+    pub fn with_ca(mut self, pem: &str) -> Result<Self> {
+        if self.tls.is_none() {
+            return Ok(self);
+        }
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_bytes()))
+            .map_err(|e| Error::Secret(format!("vault CA: {}", e)))?;
+        if certs.is_empty() {
+            return Err(Error::Secret("vault CA has no certificates".to_string()));
+        }
+
+        let mut roots = RootCertStore::empty();
+        for cert in certs {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|e| Error::Secret(format!("vault CA: {}", e)))?;
+        }
+
+        self.tls = Some(tls_connector(roots));
+        Ok(self)
+    }
+
+    /// Builds the provider from the VAULT_ADDR, VAULT_TOKEN and VAULT_CACERT
+    /// environment variables, giving back None when no address is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let address = match std::env::var("VAULT_ADDR") {
+            Ok(address) => address,
+            Err(_) => return Ok(None),
+        };
+
+        let token = std::env::var("VAULT_TOKEN").unwrap_or_default();
+        let provider = VaultProvider::new(&address, &token)?;
+
+        match std::env::var("VAULT_CACERT") {
+            Ok(path) => {
+                let pem = std::fs::read_to_string(&path)
+                    .map_err(|e| Error::Secret(format!("vault CA '{}': {}", path, e)))?;
+                provider.with_ca(&pem).map(Some)
+            }
+            Err(_) => Ok(Some(provider)),
+        }
+    }
+
+    /// Sends a request through a new connection, which is enough for the
+    /// few requests made by refreshes.
+    async fn send(
+        &self,
+        request: hyper::Request<hyper::Body>,
+    ) -> std::result::Result<hyper::Response<hyper::Body>, String> {
+        let host = self.address.host().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = match self.address.port_u16() {
+            Some(port) => port,
+            None if self.tls.is_some() => 443,
+            None => 80,
+        };
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match &self.tls {
+            None => exchange(stream, request).await,
+            Some(tls) => {
+                let name = ServerName::try_from(host).map_err(|e| e.to_string())?;
+                let stream = tls.connect(name, stream).await.map_err(|e| e.to_string())?;
+
+                exchange(stream, request).await
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for VaultProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("VaultProvider")
+            .field("address", &self.address)
+            .field("token", &self.token)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+/// Stands for a provider that could not be built, failing only when one of
+/// its secrets is used, so that services not using them still start.
+#[derive(Debug)]
+struct UnavailableProvider {
+    scheme: String,
+    error: String,
+}
+
+#[tonic::async_trait]
+impl SecretProvider for UnavailableProvider {
+    fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String> {
+        Err(secret_error(path, &self.error))
+    }
+}
+
+#[tonic::async_trait]
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &str {
+        "vault"
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String> {
+        let (path, key) = vault_path(path)?;
+        let prefix = self.address.path().trim_end_matches('/');
+        let request = hyper::Request::get(format!("{}/v1/{}", prefix, path))
+            .header(
+                hyper::header::HOST,
+                self.address.authority().unwrap().as_str(),
+            )
+            .header("X-Vault-Token", self.token.expose())
+            .body(hyper::Body::empty())
+            .map_err(|e| secret_error(path, &e.to_string()))?;
+
+        let response = self
+            .send(request)
+            .await
+            .map_err(|e| secret_error(path, &e))?;
+
+        if !response.status().is_success() {
+            return Err(secret_error(
+                path,
+                &format!("vault answered with {}", response.status()),
+            ));
+        }
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| secret_error(path, &e.to_string()))?;
+        let data: Value =
+            serde_json::from_slice(&body).map_err(|e| secret_error(path, &e.to_string()))?;
+
+        // KV version 2 nests the secret inside another data object.
+        match data["data"].get("data").filter(|d| d.is_object()) {
+            Some(data) => vault_value(data, path, key),
+            None => vault_value(&data["data"], path, key),
+        }
+    }
+}
+
+type RotationCallback = Arc<dyn Fn(&Secret) + Send + Sync>;
+
+/// The secret providers of a service, with the values already fetched.
+pub struct Secrets {
+    providers: HashMap<String, Arc<dyn SecretProvider>>,
+    cache: Mutex<HashMap<String, Secret>>,
+    callbacks: Mutex<HashMap<String, Vec<RotationCallback>>>,
+}
+
+impl Secrets {
+    /// Registers the file and env providers, the given ones, which replace
+    /// them when using the same scheme, and a Vault provider when VAULT_ADDR
+    /// is set and no other provider handles the `vault` scheme. When the
+    /// Vault settings are invalid, only resolving `vault:` references fails.
+    pub(crate) fn new(providers: &[Arc<dyn SecretProvider>]) -> Self {
+        let mut registered: HashMap<String, Arc<dyn SecretProvider>> = HashMap::new();
+        let defaults: [Arc<dyn SecretProvider>; 2] =
+            [Arc::new(FileProvider), Arc::new(EnvProvider)];
+
+        for provider in defaults.iter().chain(providers) {
+            registered.insert(provider.scheme().to_string(), provider.clone());
+        }
+
+        if !registered.contains_key("vault") {
+            let vault: Option<Arc<dyn SecretProvider>> = match VaultProvider::from_env() {
+                Ok(vault) => vault.map(|v| Arc::new(v) as Arc<dyn SecretProvider>),
+                Err(e) => Some(Arc::new(UnavailableProvider {
+                    scheme: "vault".to_string(),
+                    error: e.to_string(),
+                })),
+            };
+
+            if let Some(vault) = vault {
+                registered.insert("vault".to_string(), vault);
+            }
+        }
+
+        Secrets {
+            providers: registered,
+            cache: Mutex::new(HashMap::new()),
+            callbacks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Gives back the reference of a settings value, when it has the
+    /// `secret:` prefix.
+    pub fn reference<'a>(&self, value: &'a str) -> Option<&'a str> {
+        value.strip_prefix(REFERENCE_PREFIX)
+    }
+
+    /// Tells if a settings value is a reference to a secret, i.e., if it has
+    /// the `secret:` prefix.
+    pub fn is_reference(&self, value: &str) -> bool {
+        self.reference(value).is_some()
+    }
+
+    /// Gives back the secret of a reference, fetching it only once.
+    pub async fn get(&self, reference: &str) -> Result<Secret> {
+        if let Some(secret) = self.cache.lock().unwrap().get(reference) {
+            return Ok(secret.clone());
+        }
+
+        let secret = self.fetch(reference).await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(reference.to_string(), secret.clone());
+
+        Ok(secret)
+    }
+
+    /// Gives back the secret of a settings value with the `secret:` prefix,
+    /// or the value itself when it has none.
+    pub async fn resolve(&self, value: &str) -> Result<Secret> {
+        match self.reference(value) {
+            Some(reference) => self.get(reference).await,
+            None => Ok(Secret::new(value)),
+        }
+    }
+
+    /// Registers a callback called with the new value of a secret when a
+    /// refresh finds out it was rotated.
+    pub fn on_rotation<F>(&self, reference: &str, callback: F)
+    where
+        F: Fn(&Secret) + Send + Sync + 'static,
+    {
+        self.callbacks
+            .lock()
+            .unwrap()
+            .entry(reference.to_string())
+            .or_default()
+            .push(Arc::new(callback));
+    }
+
+    /// Fetches again every secret already used, calling the rotation
+    /// callbacks of the ones that changed. Secrets that could not be fetched
+    /// keep their previous values.
+    pub async fn refresh(&self) -> Result<()> {
+        let references: Vec<String> = self.cache.lock().unwrap().keys().cloned().collect();
+        let mut errors = Vec::new();
+
+        for reference in references {
+            let secret = match self.fetch(&reference).await {
+                Ok(secret) => secret,
+                Err(e) => {
+                    errors.push(e.to_string());
+                    continue;
+                }
+            };
+
+            let previous = self
+                .cache
+                .lock()
+                .unwrap()
+                .insert(reference.clone(), secret.clone());
+
+            if previous.as_ref() == Some(&secret) {
+                continue;
+            }
+
+            let callbacks = self
+                .callbacks
+                .lock()
+                .unwrap()
+                .get(&reference)
+                .cloned()
+                .unwrap_or_default();
+
+            for callback in callbacks {
+                callback(&secret);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Secret(errors.join("; ")))
+        }
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<Secret> {
+        match self.split(reference) {
+            Some((provider, path)) => provider.fetch(path).await.map(Secret),
+            None => Err(Error::Secret(format!(
+                "'{}' does not use a known scheme",
+                redact_reference(reference)
+            ))),
+        }
+    }
+
+    fn split<'a>(&self, reference: &'a str) -> Option<(&Arc<dyn SecretProvider>, &'a str)> {
+        let (scheme, path) = reference.split_once(':')?;
+        let provider = self.providers.get(scheme)?;
+        let path = path.strip_prefix("//").unwrap_or(path);
+
+        Some((provider, path))
+    }
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut schemes: Vec<&String> = self.providers.keys().collect();
+        schemes.sort();
+
+        f.debug_struct("Secrets")
+            .field("schemes", &schemes)
+            .field("cached", &self.cache.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Splits Vault references, like `secret/data/db#password`, into path and
+/// key.
+fn vault_path(path: &str) -> Result<(&str, &str)> {
+    match path.split_once('#') {
+        Some((path, key)) if !path.is_empty() && !key.is_empty() => Ok((path, key)),
+        _ => Err(secret_error(
+            path,
+            "vault references need a path and a key, like 'secret/data/db#password'",
+        )),
+    }
+}
+
+fn vault_value(data: &Value, path: &str, key: &str) -> Result<String> {
+    match &data[key] {
+        Value::String(value) => Ok(value.clone()),
+        Value::Null => Err(secret_error(path, &format!("key '{}' not found", key))),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// Keeps only the scheme of unknown references, which may be secret values
+/// themselves.
+fn redact_reference(reference: &str) -> String {
+    match reference.split_once(':') {
+        Some((scheme, _)) => format!("{}:***", scheme),
+        None => "***".to_string(),
+    }
+}
+
+fn tls_connector(roots: RootCertStore) -> TlsConnector {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Sends a request through a connection, keeping it until the response body
+/// is read.
+async fn exchange<S>(
+    stream: S,
+    request: hyper::Request<hyper::Body>,
+) -> std::result::Result<hyper::Response<hyper::Body>, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::spawn(connection);
+
+    sender
+        .send_request(request)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn secret_error(path: &str, message: &str) -> Error {
+    Error::Secret(format!("{}: {}", path, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    pub async fn test_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let file = dir.join("db");
+        std::fs::write(&file, "first\n").unwrap();
+
+        let vault = dir.join("vault.json");
+        std::fs::write(&vault, r#"{"secret/data/db": {"password": "example"}}"#).unwrap();
+        let mut env = crate::testing::EnvGuard::new();
+        env.set("TEST_SECRETS_PASSWORD", "from-env");
+
+        let secrets = Secrets::new(&[Arc::new(LocalVault::new(&vault))]);
+        let reference = format!("file://{}", file.display());
+
+        let secret = secrets.get(&reference).await.unwrap();
+        assert_eq!(secret.expose(), "first");
+        assert_eq!(format!("{:?} {}", secret, secret), "Secret(***) ***");
+
+        assert_eq!(
+            secrets.get("env:TEST_SECRETS_PASSWORD").await.unwrap(),
+            Secret::new("from-env")
+        );
+        assert_eq!(
+            secrets
+                .get("vault:secret/data/db#password")
+                .await
+                .unwrap()
+                .expose(),
+            "example"
+        );
+        assert!(secrets.get("vault:secret/data/db").await.is_err());
+        assert_eq!(
+            secrets.resolve("plain:value").await.unwrap().expose(),
+            "plain:value"
+        );
+
+        // Only values with the secret: prefix are references.
+        assert_eq!(
+            secrets.resolve("env:TEST_SECRETS_PASSWORD").await.unwrap(),
+            Secret::new("env:TEST_SECRETS_PASSWORD")
+        );
+        assert_eq!(
+            secrets
+                .resolve("secret:env:TEST_SECRETS_PASSWORD")
+                .await
+                .unwrap(),
+            Secret::new("from-env")
+        );
+        assert!(secrets.resolve("secret:unknown:db").await.is_err());
+
+        let rotations = Arc::new(AtomicUsize::new(0));
+        let counter = rotations.clone();
+        secrets.on_rotation(&reference, move |secret| {
+            assert_eq!(secret.expose(), "second");
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        secrets.refresh().await.unwrap();
+        assert_eq!(rotations.load(Ordering::SeqCst), 0);
+
+        std::fs::write(&file, "second\n").unwrap();
+        secrets.refresh().await.unwrap();
+        assert_eq!(rotations.load(Ordering::SeqCst), 1);
+        assert_eq!(secrets.get(&reference).await.unwrap().expose(), "second");
+    }
+
+    #[tokio::test]
+    pub async fn test_vault_provider() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let n = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_lowercase();
+
+            let body = r#"{"data": {"data": {"password": "example"}}}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+
+            request
+        });
+
+        let provider = VaultProvider::new(&address, "token").unwrap();
+        assert_eq!(
+            provider.fetch("secret/data/db#password").await.unwrap(),
+            "example"
+        );
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get /v1/secret/data/db "));
+        assert!(request.contains("x-vault-token: token"));
+        assert!(format!("{:?}", provider).contains("Secret(***)"));
+
+        assert!(VaultProvider::new("https://vault:8200", "token").is_ok());
+        assert!(VaultProvider::new("ftp://vault:8200", "token").is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_vault_provider_https() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{PrivateKey, ServerConfig};
+
+        const CA: &str = include_str!("../grpc/testdata/ca.pem");
+        const SERVER_CERT: &str = include_str!("../grpc/testdata/server.pem");
+        const SERVER_KEY: &str = include_str!("../grpc/testdata/server.key");
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(SERVER_CERT.as_bytes())).unwrap();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(SERVER_KEY.as_bytes()))
+            .unwrap()
+            .remove(0);
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                certs.into_iter().map(Certificate).collect(),
+                PrivateKey(key),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!(
+            "https://localhost:{}",
+            listener.local_addr().unwrap().port()
+        );
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut request = [0; 1024];
+            let n = stream.read(&mut request).await.unwrap();
+
+            let body = r#"{"data": {"password": "example"}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.flush().await.unwrap();
+
+            String::from_utf8_lossy(&request[..n]).to_lowercase()
+        });
+
+        let provider = VaultProvider::new(&address, "token")
+            .unwrap()
+            .with_ca(CA)
+            .unwrap();
+        assert_eq!(
+            provider.fetch("secret/db#password").await.unwrap(),
+            "example"
+        );
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("get /v1/secret/db "));
+        assert!(request.contains("x-vault-token: token"));
+    }
+
+    #[tokio::test]
+    pub async fn test_secrets_vault_env() {
+        let mut env = crate::testing::EnvGuard::new();
+        env.set("VAULT_ADDR", "https://vault.example.com:8200")
+            .set("VAULT_TOKEN", "token");
+
+        let secrets = Secrets::new(&[]);
+        assert!(format!("{:?}", secrets).contains("vault"));
+
+        // Invalid Vault settings only fail the references to Vault secrets.
+        env.set("VAULT_ADDR", "vault:8200");
+        let secrets = Secrets::new(&[]);
+        assert!(secrets.get("vault:secret/db#password").await.is_err());
+        assert!(secrets.get("env:VAULT_TOKEN").await.is_ok());
+    }
+}
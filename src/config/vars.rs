@@ -0,0 +1,98 @@
+// Environment variables that are not plain strings. Services fall back to
+// their other settings when one of them can not be parsed, so the loaders
+// read them through this table, which `pocket check` goes through to report
+// the invalid ones.
+
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use crate::config::{Config, GetEnv};
+
+/// An environment variable holding a `T`.
+#[derive(Debug)]
+pub(crate) struct EnvVar<T> {
+    pub name: &'static str,
+    expected: &'static str,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T: FromStr> EnvVar<T> {
+    pub const fn new(name: &'static str, expected: &'static str) -> Self {
+        EnvVar {
+            name,
+            expected,
+            value: PhantomData,
+        }
+    }
+
+    /// Gives back the value of the variable, or `default` when it is not set
+    /// or can not be parsed.
+    pub fn get(&self, default: Option<T>) -> Option<T> {
+        Config::get_os_env(self.name, default)
+    }
+
+    fn problem(&self) -> Option<(&'static str, String)> {
+        let value = std::env::var(self.name).ok()?;
+        match value.parse::<T>() {
+            Ok(_) => None,
+            Err(_) => Some((self.name, format!("'{}' is not {}", value, self.expected))),
+        }
+    }
+}
+
+/// The environment variables of a CORS section, named after `prefix`.
+#[derive(Debug)]
+pub(crate) struct CorsVars {
+    pub prefix: &'static str,
+    pub credentials: EnvVar<bool>,
+    pub max_age: EnvVar<u64>,
+}
+
+pub(crate) const HTTP_CORS: CorsVars = CorsVars {
+    prefix: "HTTP_CORS",
+    credentials: HTTP_CORS_CREDENTIALS,
+    max_age: HTTP_CORS_MAX_AGE,
+};
+
+pub(crate) const GRPC_CORS: CorsVars = CorsVars {
+    prefix: "GRPC_CORS",
+    credentials: GRPC_CORS_CREDENTIALS,
+    max_age: GRPC_CORS_MAX_AGE,
+};
+
+macro_rules! env_vars {
+    ($($name:ident: $type:ty => $expected:literal,)*) => {
+        $(
+            pub(crate) const $name: EnvVar<$type> = EnvVar::new(stringify!($name), $expected);
+        )*
+
+        /// Gives back the variables of the table that are set but can not
+        /// be parsed, with the reason.
+        pub(crate) fn problems() -> Vec<(&'static str, String)> {
+            [$($name.problem(),)*].into_iter().flatten().collect()
+        }
+    };
+}
+
+env_vars! {
+    SERVICE_DUAL_STACK: bool => "a boolean",
+    SERVICE_TIMEOUT: u64 => "a number of seconds",
+    SERVICES_GRPC_PORT: u16 => "a port number",
+    DATABASE_PORT: i32 => "a port number",
+    DATABASE_TLS: bool => "a boolean",
+    DATABASE_RETRY_WRITES: bool => "a boolean",
+    DATABASE_MAX_POOL_SIZE: u32 => "a number",
+    DATABASE_CONNECT_TIMEOUT_MS: u64 => "a number of milliseconds",
+    DATABASE_SERVER_SELECTION_TIMEOUT_MS: u64 => "a number of milliseconds",
+    DATABASE_CONNECT_RETRIES: u32 => "a number",
+    DATABASE_CONNECT_RETRY_BACKOFF_MS: u64 => "a number of milliseconds",
+    GRPC_WEB: bool => "a boolean",
+    GRPC_CORS_CREDENTIALS: bool => "a boolean",
+    GRPC_CORS_MAX_AGE: u64 => "a number of seconds",
+    GRPC_TLS_CLIENT_AUTH_OPTIONAL: bool => "a boolean",
+    GRPC_TLS_RELOAD_INTERVAL: u64 => "a number of seconds",
+    HTTP_CORS_CREDENTIALS: bool => "a boolean",
+    HTTP_CORS_MAX_AGE: u64 => "a number of seconds",
+    CONFIG_RELOAD_INTERVAL: u64 => "a number of seconds",
+    SECRETS_REFRESH_INTERVAL: u64 => "a number of seconds",
+}
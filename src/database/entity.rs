@@ -0,0 +1,327 @@
+// Typed access to records of prost messages, where each message type knows
+// its own collection and ID field.
+
+use std::marker::PhantomData;
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, Bson, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument, UpdateModifications},
+    Collection,
+};
+use prost_types::FieldMask;
+
+use crate::database::{mask, DatabaseResult};
+use crate::grpc::rpc;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+/// A prost message that is stored as a database record.
+///
+/// Its ID field is stored as the record `_id`, so lookups by ID use the
+/// collection primary index. Use the [`entity!`](crate::entity) macro to
+/// implement it for generated types.
+pub trait Entity:
+    prost::Message + serde::Serialize + serde::de::DeserializeOwned + Unpin + Send + Sync
+{
+    /// The collection where records of this type are stored.
+    const COLLECTION: &'static str;
+
+    /// The message field holding the record ID.
+    const ID_FIELD: &'static str = "id";
+}
+
+/// Implements [`Entity`] for a prost message.
+///
+/// ```ignore
+/// pocket::entity!(example::Example, collection = "examples");
+/// pocket::entity!(example::Account, collection = "accounts", id = "account_id");
+/// ```
+#[macro_export]
+macro_rules! entity {
+    ($t:ty, collection = $collection:expr) => {
+        $crate::entity!($t, collection = $collection, id = "id");
+    };
+    ($t:ty, collection = $collection:expr, id = $id:expr) => {
+        impl $crate::database::entity::Entity for $t {
+            const COLLECTION: &'static str = $collection;
+            const ID_FIELD: &'static str = $id;
+        }
+    };
+}
+
+/// Pagination options for listing records. The page token is the one
+/// returned by a previous [`Page`], or empty for the first page.
+#[derive(Clone, Debug, Default)]
+pub struct Pagination {
+    pub page_size: u32,
+    pub page_token: String,
+}
+
+/// A page of records.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    /// The token to retrieve the next page, empty when there are no more
+    /// records.
+    pub next_page_token: String,
+}
+
+/// Gives CRUD operations over the collection of an [`Entity`].
+#[derive(Debug)]
+pub struct Repository<T: Entity> {
+    collection: Collection<Document>,
+    entity: PhantomData<T>,
+}
+
+impl<T: Entity> Repository<T> {
+    pub(crate) fn new(collection: Collection<Document>) -> Self {
+        Repository {
+            collection,
+            entity: PhantomData,
+        }
+    }
+
+    /// Inserts a new record.
+    pub async fn insert(&self, source: &T) -> DatabaseResult<()> {
+        let record = to_record(source)?;
+
+        match self.collection.insert_one(record, None).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(rpc::Error::from(e).to_status()),
+        }
+    }
+
+    /// Finds a single record by its ID.
+    pub async fn find_by_id(&self, id: &str) -> DatabaseResult<T> {
+        self.find_one(doc! {"_id": id}).await
+    }
+
+    /// Finds a single record using a custom filter.
+    pub async fn find_one(&self, filter: Document) -> DatabaseResult<T> {
+        match self.collection.find_one(filter, None).await {
+            Ok(Some(record)) => from_record(record),
+            Ok(None) => Err(rpc::Error::new(rpc::ErrorCode::NotFound, None).to_status()),
+            Err(e) => Err(rpc::Error::from(e).to_status()),
+        }
+    }
+
+    /// Lists records matching a custom filter, one page at a time. Records
+    /// are ordered by their ID, and each page starts after the last ID of
+    /// the previous one, so that records inserted or deleted in between do
+    /// not shift the pages.
+    pub async fn find(&self, filter: Document, pagination: &Pagination) -> DatabaseResult<Page<T>> {
+        let filter = match decode_page_token(&pagination.page_token)? {
+            Some(last) => doc! {"$and": [filter, {"_id": {"$gt": last}}]},
+            None => filter,
+        };
+        let page_size = match pagination.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+
+        // One extra record tells whether there is a next page.
+        let options = FindOptions::builder()
+            .sort(doc! {"_id": 1})
+            .limit(i64::from(page_size) + 1)
+            .build();
+
+        let records: Vec<Document> = match self.collection.find(filter, options).await {
+            Ok(cursor) => cursor.try_collect().await.map_err(rpc::Error::from)?,
+            Err(e) => return Err(rpc::Error::from(e).to_status()),
+        };
+
+        let has_next = records.len() > page_size as usize;
+        let records: Vec<Document> = records.into_iter().take(page_size as usize).collect();
+
+        let next_page_token = match records.last().and_then(|r| r.get("_id")) {
+            Some(last) if has_next => encode_page_token(last)?,
+            _ => String::new(),
+        };
+
+        Ok(Page {
+            items: records
+                .into_iter()
+                .map(from_record)
+                .collect::<DatabaseResult<Vec<T>>>()?,
+            next_page_token,
+        })
+    }
+
+    /// Counts the records matching a custom filter.
+    pub async fn count(&self, filter: Document) -> DatabaseResult<u64> {
+        self.collection
+            .count_documents(filter, None)
+            .await
+            .map_err(|e| rpc::Error::from(e).to_status())
+    }
+
+    /// Sets the fields of a record and gives back its updated version.
+    pub async fn update(&self, id: &str, source: Document) -> DatabaseResult<T> {
+        if source.contains_key(T::ID_FIELD) || source.contains_key("_id") {
+            return Err(rpc::Error::new(
+                rpc::ErrorCode::Validation,
+                Some("the record ID cannot be updated"),
+            )
+            .to_status());
+        }
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let result = self
+            .collection
+            .find_one_and_update(
+                doc! {"_id": id},
+                UpdateModifications::Document(doc! {"$set": source}),
+                options,
+            )
+            .await;
+
+        match result {
+            Ok(Some(record)) => from_record(record),
+            Ok(None) => Err(rpc::Error::new(rpc::ErrorCode::NotFound, None).to_status()),
+            Err(e) => Err(rpc::Error::from(e).to_status()),
+        }
+    }
+
+    /// Updates only the given fields of a record, taking their values from
+    /// `source`. Nested fields are referenced with dots, like `address.city`.
+    pub async fn update_fields(&self, id: &str, source: &T, fields: &[&str]) -> DatabaseResult<T> {
+        if fields.contains(&T::ID_FIELD) {
+            return Err(rpc::Error::new(
+                rpc::ErrorCode::Validation,
+                Some("the record ID cannot be updated"),
+            )
+            .to_status());
+        }
+
+        let record = to_record(source)?;
+        let set = mask::masked_fields(&record, fields.iter().copied())?;
+        self.update(id, set).await
+    }
+
+    /// Updates the fields of a record selected by a field mask, following the
+    /// standard partial update semantics of Update RPCs.
+    pub async fn update_with_mask(
+        &self,
+        id: &str,
+        source: &T,
+        mask: &FieldMask,
+    ) -> DatabaseResult<T> {
+        let fields: Vec<&str> = mask.paths.iter().map(|p| p.as_str()).collect();
+        self.update_fields(id, source, &fields).await
+    }
+
+    /// Deletes a single record and gives it back.
+    pub async fn delete(&self, id: &str) -> DatabaseResult<T> {
+        match self
+            .collection
+            .find_one_and_delete(doc! {"_id": id}, None)
+            .await
+        {
+            Ok(Some(record)) => from_record(record),
+            Ok(None) => Err(rpc::Error::new(rpc::ErrorCode::NotFound, None).to_status()),
+            Err(e) => Err(rpc::Error::from(e).to_status()),
+        }
+    }
+}
+
+fn to_record<T: Entity>(source: &T) -> DatabaseResult<Document> {
+    let mut record = bson::to_document(source).map_err(internal)?;
+
+    if let Some(id) = record.remove(T::ID_FIELD) {
+        record.insert("_id", id);
+    }
+
+    Ok(record)
+}
+
+fn from_record<T: Entity>(mut record: Document) -> DatabaseResult<T> {
+    if let Some(id) = record.remove("_id") {
+        record.insert(T::ID_FIELD, id);
+    }
+
+    bson::from_bson(Bson::Document(record)).map_err(internal)
+}
+
+/// Page tokens hold the last ID of a page, as a BSON document, so that IDs
+/// of any type can be compared by the database.
+fn encode_page_token(last: &Bson) -> DatabaseResult<String> {
+    let mut bytes = Vec::new();
+    doc! {"_id": last.clone()}
+        .to_writer(&mut bytes)
+        .map_err(internal)?;
+
+    Ok(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+}
+
+fn decode_page_token(token: &str) -> DatabaseResult<Option<Bson>> {
+    if token.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid =
+        || rpc::Error::new(rpc::ErrorCode::Validation, Some("invalid page token")).to_status();
+
+    let bytes = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+    let mut document = Document::from_reader(&mut bytes.as_slice()).map_err(|_| invalid())?;
+    document.remove("_id").map(Some).ok_or_else(invalid)
+}
+
+fn internal<E: std::fmt::Display>(e: E) -> tonic::Status {
+    rpc::Error::new(rpc::ErrorCode::Internal, Some(&e.to_string())).to_status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    struct Example {
+        #[prost(string, tag = "1")]
+        pub example_id: String,
+        #[prost(string, tag = "2")]
+        pub name: String,
+    }
+
+    crate::entity!(Example, collection = "examples", id = "example_id");
+
+    #[test]
+    pub fn test_entity_record_mapping() {
+        let example = Example {
+            example_id: "ex_1".to_string(),
+            name: "first".to_string(),
+        };
+
+        let record = to_record(&example).unwrap();
+        assert_eq!(record, doc! {"name": "first", "_id": "ex_1"});
+        assert_eq!(from_record::<Example>(record).unwrap(), example);
+    }
+
+    #[test]
+    pub fn test_page_token() {
+        assert_eq!(decode_page_token("").unwrap(), None);
+
+        for last in [
+            Bson::from("ex_150"),
+            Bson::from(150),
+            Bson::ObjectId(bson::oid::ObjectId::new()),
+        ] {
+            let token = encode_page_token(&last).unwrap();
+            assert_eq!(decode_page_token(&token).unwrap(), Some(last));
+        }
+
+        assert!(decode_page_token("abc").is_err());
+        assert!(decode_page_token("150").is_err());
+
+        let mut bytes = Vec::new();
+        doc! {"id": 1}.to_writer(&mut bytes).unwrap();
+        let token = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        assert!(decode_page_token(&token).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+// Conversion of google.protobuf.FieldMask paths into MongoDB update
+// documents, so Update RPCs only touch the fields a client asked for.
+
+use mongodb::bson::{self, Bson, Document};
+use prost_types::FieldMask;
+
+use crate::database::DatabaseResult;
+use crate::grpc::rpc;
+
+/// Builds the `$set` content of an update from the masked paths of `source`.
+/// Nested paths, such as `address.city`, only change the inner field. A path
+/// that crosses a message unset in `source` sets that whole message instead,
+/// clearing it, since MongoDB cannot set fields inside a null value.
+pub fn update_document<T: serde::Serialize>(
+    source: &T,
+    mask: &FieldMask,
+) -> DatabaseResult<Document> {
+    let record = match bson::to_document(source) {
+        Ok(record) => record,
+        Err(e) => {
+            return Err(rpc::Error::new(rpc::ErrorCode::Internal, Some(&e.to_string())).to_status())
+        }
+    };
+
+    masked_fields(&record, mask.paths.iter().map(|p| p.as_str()))
+}
+
+pub(crate) fn masked_fields<'a>(
+    record: &Document,
+    paths: impl Iterator<Item = &'a str>,
+) -> DatabaseResult<Document> {
+    let mut set = Document::new();
+
+    for path in paths {
+        match masked_value(record, path) {
+            Some((key, value)) => {
+                // Paths inside a field that is already set are covered by it,
+                // and setting both would conflict.
+                if set.keys().any(|k| covers(k, &key)) {
+                    continue;
+                }
+
+                let covered: Vec<String> =
+                    set.keys().filter(|k| covers(&key, k)).cloned().collect();
+                for k in covered {
+                    set.remove(&k);
+                }
+
+                set.insert(key, value);
+            }
+            None => {
+                return Err(rpc::Error::new(
+                    rpc::ErrorCode::Validation,
+                    Some(&format!("invalid field mask path '{}'", path)),
+                )
+                .to_status())
+            }
+        }
+    }
+
+    if set.is_empty() {
+        return Err(
+            rpc::Error::new(rpc::ErrorCode::Validation, Some("empty field mask")).to_status(),
+        );
+    }
+
+    Ok(set)
+}
+
+/// Gives back the key to set for a path, with its value. It is the path
+/// itself, or its first unset message.
+fn masked_value(record: &Document, path: &str) -> Option<(String, Bson)> {
+    let fields: Vec<&str> = path.split('.').collect();
+    if fields.iter().any(|f| f.is_empty() || f.starts_with('$')) {
+        return None;
+    }
+
+    let mut current = record;
+    for (i, field) in fields.iter().enumerate() {
+        let value = current.get(field)?;
+        if i == fields.len() - 1 {
+            return Some((path.to_string(), value.clone()));
+        }
+
+        match value {
+            Bson::Document(inner) => current = inner,
+            Bson::Null => return Some((fields[..=i].join("."), Bson::Null)),
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// Tells if setting `key` also sets `other`.
+fn covers(key: &str, other: &str) -> bool {
+    other.starts_with(key) && (other.len() == key.len() || other[key.len()..].starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    pub fn test_masked_fields() {
+        let record = doc! {
+            "name": "example",
+            "value": 42,
+            "address": {"city": "Curitiba", "country": "BR"},
+            "owner": Bson::Null,
+        };
+
+        let set = masked_fields(&record, ["name", "address.city"].into_iter()).unwrap();
+        assert_eq!(set, doc! {"name": "example", "address.city": "Curitiba"});
+
+        let set = masked_fields(&record, ["owner.name", "owner.email"].into_iter()).unwrap();
+        assert_eq!(set, doc! {"owner": Bson::Null});
+
+        let set = masked_fields(&record, ["address.city", "address"].into_iter()).unwrap();
+        assert_eq!(set, doc! {"address": {"city": "Curitiba", "country": "BR"}});
+
+        assert!(masked_fields(&record, ["unknown"].into_iter()).is_err());
+        assert!(masked_fields(&record, ["name.first"].into_iter()).is_err());
+        assert!(masked_fields(&record, ["address."].into_iter()).is_err());
+        assert!(masked_fields(&record, std::iter::empty()).is_err());
+    }
+
+    #[derive(Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+    struct Record {
+        name: String,
+        owner: Option<Owner>,
+    }
+
+    #[derive(Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+    struct Owner {
+        name: String,
+        email: String,
+    }
+
+    // Applies a `$set` like MongoDB does, failing to create fields inside a
+    // null value.
+    fn apply(record: &mut Document, set: &Document) -> std::result::Result<(), String> {
+        for (path, value) in set {
+            let mut current = &mut *record;
+            let mut fields: Vec<&str> = path.split('.').collect();
+            let last = fields.pop().unwrap();
+
+            for field in fields {
+                current = match current.get_mut(field) {
+                    Some(Bson::Document(inner)) => inner,
+                    _ => return Err(format!("cannot create field '{}'", last)),
+                };
+            }
+
+            current.insert(last, value.clone());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_update_through_unset_message() {
+        let mut stored = bson::to_document(&Record {
+            name: "example".to_string(),
+            owner: Some(Owner {
+                name: "owner".to_string(),
+                email: "owner@example.com".to_string(),
+            }),
+        })
+        .unwrap();
+
+        let source = Record {
+            name: "changed".to_string(),
+            owner: None,
+        };
+
+        let mask = FieldMask {
+            paths: vec!["name".to_string(), "owner.name".to_string()],
+        };
+
+        let set = update_document(&source, &mask).unwrap();
+        apply(&mut stored, &set).unwrap();
+
+        let updated: Record = bson::from_document(stored.clone()).unwrap();
+        assert_eq!(updated, source);
+
+        // Setting the owner again works on the cleared record.
+        let source = Record {
+            owner: Some(Owner {
+                name: "new owner".to_string(),
+                email: String::new(),
+            }),
+            ..source
+        };
+
+        let mask = FieldMask {
+            paths: vec!["owner".to_string()],
+        };
+
+        let set = update_document(&source, &mask).unwrap();
+        apply(&mut stored, &set).unwrap();
+        let updated: Record = bson::from_document(stored).unwrap();
+        assert_eq!(updated, source);
+    }
+}
@@ -1,14 +1,74 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 use serde_derive::Deserialize;
 use validator::Validate;
 
-mod validation;
+pub(crate) mod validation;
 
-use crate::definition::validation::service_kind_oneof;
+use crate::config::{Config, GetEnv};
+use crate::definition::validation::{
+    bind_address, method_timeouts, service_kind_oneof, topic_names,
+};
 use crate::error::{Error, Result};
 
-#[derive(Debug)]
+const SETTINGS_FILE: &str = "service.toml";
+
+/// Environment variable with the path of the settings file.
+pub(crate) const SETTINGS_FILE_ENV: &str = "SERVICE_SETTINGS_FILE";
+
+/// Environment variable selecting the settings profile, like `prod`.
+pub(crate) const PROFILE_ENV: &str = "SERVICE_PROFILE";
+
+/// Sections of the settings file applied again when it is reloaded, every
+/// other one needs a restart.
+const RELOADABLE_SECTIONS: [&str; 3] = ["config", "flags", "logging"];
+
+#[derive(Debug, Deserialize)]
 pub(crate) struct ServiceDefinition {
+    #[serde(flatten)]
     pub info: ServiceInfo,
+
+    #[serde(default)]
+    pub server: ServerDefinition,
+
+    #[serde(default)]
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub database: DatabaseDefinition,
+
+    #[serde(default)]
+    pub logging: LoggingDefinition,
+
+    /// Other services used by this one, by name.
+    #[serde(default)]
+    pub clients: HashMap<String, ClientDefinition>,
+
+    #[serde(default)]
+    pub pubsub: PubsubDefinition,
+
+    #[serde(default)]
+    pub http: HttpDefinition,
+
+    #[serde(default)]
+    pub grpc: GrpcDefinition,
+
+    #[serde(default)]
+    pub flags: HashMap<String, FlagDefinition>,
+
+    /// The `[config]` section, with the typed settings of the service.
+    pub config: Option<toml::Value>,
+
+    /// The profile merged over the settings file.
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Where the settings file was loaded from.
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    /// The settings file merged with its profile, as loaded.
+    #[serde(skip)]
+    pub content: toml::value::Table,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -21,6 +81,221 @@ pub(crate) struct ServiceInfo {
     pub kind: String,
 }
 
+/// The `[server]` section of the settings file.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub(crate) struct ServerDefinition {
+    /// Port 0 picks an ephemeral one.
+    #[validate(range(max = 65535))]
+    pub port: Option<u32>,
+
+    /// An IP address, like `0.0.0.0` or `::`, or a Unix domain socket path,
+    /// like `unix:/run/examples.sock`.
+    #[validate(custom(function = "bind_address"))]
+    pub address: Option<String>,
+
+    /// Accepts IPv4 connections at an IPv6 address too.
+    pub dual_stack: Option<bool>,
+
+    /// Request timeout, in seconds, of gRPC services.
+    #[validate(range(min = 1))]
+    pub timeout: Option<u64>,
+
+    /// Request timeouts, in seconds, of gRPC methods, like
+    /// `"examples.Examples/Search" = 60`, or of all methods of a service,
+    /// like `"examples.Examples" = 10`.
+    #[serde(default)]
+    #[validate(custom(function = "method_timeouts"))]
+    pub timeouts: HashMap<String, u64>,
+}
+
+/// The `[database]` section of the settings file. Durations are in seconds,
+/// and the password (or the URI) may be a secret reference.
+#[derive(Debug, Deserialize, Validate)]
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+pub(crate) struct DatabaseDefinition {
+    #[serde(default = "database_enabled_default")]
+    pub enabled: bool,
+
+    #[validate(length(min = 1))]
+    pub name: Option<String>,
+    pub collection: Option<String>,
+
+    pub host: Option<String>,
+    #[validate(range(min = 1, max = 65535))]
+    pub port: Option<i32>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub uri: Option<String>,
+    pub tls_cacert_path: Option<String>,
+    pub tls_certificate_key_path: Option<String>,
+
+    pub tls: Option<bool>,
+    pub replica_set: Option<String>,
+    pub read_preference: Option<String>,
+    pub write_concern: Option<String>,
+    pub retry_writes: Option<bool>,
+    #[validate(range(min = 1))]
+    pub max_pool_size: Option<u32>,
+    /// Durations are in milliseconds, like their environment variables.
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+    pub connect_retries: Option<u32>,
+    pub connect_retry_backoff_ms: Option<u64>,
+}
+
+/// The `[logging]` section of the settings file.
+#[derive(Debug, Deserialize, Validate)]
+pub(crate) struct LoggingDefinition {
+    /// Fields added to every log entry, like `region = "us-east-1"`.
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+
+    /// Logs every request of HTTP services.
+    #[serde(default = "access_log_default")]
+    pub access_log: bool,
+
+    #[serde(default)]
+    pub level: LogLevel,
+}
+
+/// The least important messages the service logs, set with the `level` of
+/// the `[logging]` section.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+/// A service of the `[clients]` section of the settings file, like:
+///
+/// ```toml
+/// [clients.payments]
+/// address = "http://payments:9090"
+/// timeout = 5
+/// ```
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub(crate) struct ClientDefinition {
+    #[validate(url)]
+    pub address: Option<String>,
+
+    /// Request timeout, in seconds.
+    #[validate(range(min = 1))]
+    pub timeout: Option<u64>,
+
+    /// Connection timeout, in seconds.
+    #[validate(range(min = 1))]
+    pub connect_timeout: Option<u64>,
+}
+
+/// The `[pubsub]` section of the settings file, with the topics a service
+/// publishes to and the ones it subscribes to.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub(crate) struct PubsubDefinition {
+    #[serde(default)]
+    #[validate(custom(function = "topic_names"))]
+    pub topics: Vec<String>,
+
+    #[serde(default)]
+    #[validate(custom(function = "topic_names"))]
+    pub subscriptions: Vec<String>,
+}
+
+/// The `[http]` section of the settings file, used by HTTP services.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HttpDefinition {
+    pub address: Option<String>,
+    pub workers: Option<usize>,
+    pub keep_alive: Option<u32>,
+
+    /// Body size limits, by data type, like `json = "2MiB"`.
+    #[serde(default)]
+    pub limits: HashMap<String, String>,
+
+    pub tls: Option<TlsDefinition>,
+
+    #[serde(default)]
+    pub cors: CorsDefinition,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TlsDefinition {
+    pub cert: String,
+    pub key: String,
+}
+
+/// The `[grpc]` section of the settings file, used by gRPC services.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct GrpcDefinition {
+    /// Accepts gRPC-Web requests, over HTTP/1.1 as well.
+    #[serde(default)]
+    pub web: bool,
+
+    /// Largest decoded gRPC-Web text body, like `"4MiB"`.
+    pub web_max_body_size: Option<String>,
+
+    #[serde(default)]
+    pub cors: CorsDefinition,
+
+    pub tls: Option<GrpcTlsDefinition>,
+}
+
+/// The `[grpc.tls]` section of the settings file. Certificates and keys are
+/// PEM files or secret references, like `secret:vault:secret/data/tls#key`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct GrpcTlsDefinition {
+    pub cert: String,
+    pub key: String,
+
+    /// CA certificates of the clients, which must present a certificate
+    /// signed by them (mutual TLS).
+    pub client_ca: Option<String>,
+
+    /// Accepts clients without certificates as well.
+    #[serde(default)]
+    pub client_auth_optional: bool,
+
+    /// Loads the certificates again every `reload_interval` seconds.
+    pub reload_interval: Option<u64>,
+}
+
+/// The `[http.cors]` and `[grpc.cors]` sections of the settings file. CORS
+/// is enabled when origins are set.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct CorsDefinition {
+    #[serde(default)]
+    pub origins: Vec<String>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub expose_headers: Option<Vec<String>>,
+    pub credentials: Option<bool>,
+    pub max_age: Option<u64>,
+}
+
+/// A flag of the `[flags]` section of the settings file, either only
+/// enabled or disabled, like `search = true`, or rolled out to a percentage
+/// of the contexts, bucketed by one of their attributes:
+///
+/// ```toml
+/// [flags.checkout]
+/// rollout = 30
+/// key = "user_id"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum FlagDefinition {
+    Enabled(bool),
+    Rollout {
+        #[serde(default = "flag_enabled_default")]
+        enabled: bool,
+        rollout: Option<u8>,
+        key: Option<String>,
+    },
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub(crate) enum ServiceKind {
     Unsupported,
@@ -30,37 +305,251 @@ pub(crate) enum ServiceKind {
 }
 
 impl ServiceDefinition {
-    pub fn new() -> Result<Self> {
-        let info: ServiceInfo = match toml::from_str(&Self::load_settings_file()?) {
-            Ok(content) => content,
+    /// Loads the settings file, `service.toml` from the current directory
+    /// unless a path is given. When a profile is selected, its file, like
+    /// `service.prod.toml` from the same directory, is merged over it. The
+    /// SERVICE_SETTINGS_FILE and SERVICE_PROFILE environment variables take
+    /// precedence over the arguments.
+    pub fn new(path: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let path: Option<PathBuf> =
+            Config::get_os_env(SETTINGS_FILE_ENV, path.map(|p| p.to_path_buf()));
+        let path = match path {
+            Some(path) => path,
+            None => Self::get_settings_file_path()?,
+        };
+
+        let profile: Option<String> = Config::get_os_env(PROFILE_ENV, profile.map(String::from));
+        let profile = profile.filter(|p| !p.is_empty());
+
+        let mut content = Self::load_settings_file(&path)?;
+        if let Some(profile) = &profile {
+            if !is_valid_profile(profile) {
+                return Err(Error::UnsupportedSetting(format!(
+                    "profile '{}' must have only letters, digits, '-' or '_'",
+                    profile
+                )));
+            }
+
+            let overlay = Self::load_settings_file(&Self::profile_file_path(&path, profile))?;
+            merge_values(&mut content, overlay);
+        }
+
+        let mut definition: ServiceDefinition = match content.clone().try_into() {
+            Ok(definition) => definition,
             Err(e) => return Err(Error::DefinitionParser(e.to_string())),
         };
 
-        if let Err(e) = info.validate() {
-            return Err(Error::UnsupportedSetting(e.to_string()));
+        definition.validate()?;
+        definition.profile = profile;
+        definition.path = path;
+        if let toml::Value::Table(content) = content {
+            definition.content = content;
+        }
+
+        Ok(definition)
+    }
+
+    /// Gives back the keys changed since a previous load of the settings
+    /// file that only take effect after a restart.
+    pub fn restart_keys(&self, previous: &toml::value::Table) -> Vec<String> {
+        let mut keys: Vec<&String> = self.content.keys().chain(previous.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter(|key| !RELOADABLE_SECTIONS.contains(&key.as_str()))
+            .filter(|key| self.content.get(*key) != previous.get(*key))
+            .cloned()
+            .collect()
+    }
+
+    /// Validates every section, reporting all problems at once.
+    fn validate(&self) -> Result<()> {
+        let problems: Vec<String> = self
+            .problems()
+            .into_iter()
+            .map(|(key, message)| format!("{}: {}", key, message))
+            .collect();
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        Err(Error::UnsupportedSetting(problems.join("; ")))
+    }
+
+    /// Gives back the invalid settings, as pairs of key and problem.
+    pub(crate) fn problems(&self) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = self.info.validate() {
+            problems.extend(
+                validation::problems("", &e)
+                    .into_iter()
+                    .map(|(key, message)| match key.as_str() {
+                        "kind" => ("type".to_string(), message),
+                        _ => (key, message),
+                    }),
+            );
+        }
+
+        if let Err(e) = self.server.validate() {
+            problems.extend(validation::problems("server", &e));
         }
 
-        Ok(ServiceDefinition { info })
+        if let Err(e) = self.database.validate() {
+            problems.extend(validation::problems("database", &e));
+        }
+
+        if let Err(e) = self.logging.validate() {
+            problems.extend(validation::problems("logging", &e));
+        }
+
+        let mut names: Vec<&String> = self.clients.keys().collect();
+        names.sort();
+        for name in names {
+            if let Err(e) = self.clients[name].validate() {
+                problems.extend(validation::problems(&format!("clients.{}", name), &e));
+            }
+        }
+
+        if let Err(e) = self.pubsub.validate() {
+            problems.extend(validation::problems("pubsub", &e));
+        }
+
+        if ServiceKind::from_str(&self.info.kind) == ServiceKind::Pubsub
+            && self.pubsub.topics.is_empty()
+            && self.pubsub.subscriptions.is_empty()
+        {
+            problems.push((
+                "pubsub".to_string(),
+                "services of the pubsub type need topics or subscriptions".to_string(),
+            ));
+        }
+
+        problems
+    }
+
+    /// Gives back the files the definition was loaded from.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.path.clone()];
+        if let Some(profile) = &self.profile {
+            files.push(Self::profile_file_path(&self.path, profile));
+        }
+
+        files
     }
 
-    fn load_settings_file() -> Result<String> {
-        let path = Self::get_settings_file_path()?;
+    pub(crate) fn load_settings_file(path: &Path) -> Result<toml::Value> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                return Err(Error::InternalOS(format!("{}: {}", path.display(), e)));
+            }
+        };
 
-        match std::fs::read_to_string(path.as_path()) {
-            Ok(content) => Ok(content),
-            Err(e) => Err(Error::InternalOS(e.to_string())),
+        match toml::from_str(&content) {
+            Ok(value) => Ok(value),
+            Err(e) => Err(Error::DefinitionParser(format!(
+                "{}: {}",
+                path.display(),
+                e
+            ))),
         }
     }
 
-    fn get_settings_file_path() -> Result<std::path::PathBuf> {
+    pub(crate) fn get_settings_file_path() -> Result<PathBuf> {
         match std::env::current_dir() {
             Ok(mut p) => {
-                p.push("service.toml");
+                p.push(SETTINGS_FILE);
                 Ok(p)
             }
             Err(r) => Err(Error::InternalOS(r.to_string())),
         }
     }
+
+    /// Gives back the file of a profile, named after the settings file, like
+    /// `service.<profile>.toml`.
+    pub(crate) fn profile_file_path(path: &Path, profile: &str) -> PathBuf {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "service".to_string());
+
+        path.with_file_name(format!("{}.{}.toml", stem, profile))
+    }
+}
+
+/// Merges tables recursively, replacing every other value.
+pub(crate) fn merge_values(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+pub(crate) fn is_valid_profile(profile: &str) -> bool {
+    profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Default for DatabaseDefinition {
+    fn default() -> Self {
+        DatabaseDefinition {
+            enabled: database_enabled_default(),
+            name: None,
+            collection: None,
+            host: None,
+            port: None,
+            username: None,
+            password: None,
+            uri: None,
+            tls_cacert_path: None,
+            tls_certificate_key_path: None,
+            tls: None,
+            replica_set: None,
+            read_preference: None,
+            write_concern: None,
+            retry_writes: None,
+            max_pool_size: None,
+            connect_timeout_ms: None,
+            server_selection_timeout_ms: None,
+            connect_retries: None,
+            connect_retry_backoff_ms: None,
+        }
+    }
+}
+
+impl Default for LoggingDefinition {
+    fn default() -> Self {
+        LoggingDefinition {
+            fields: HashMap::new(),
+            access_log: access_log_default(),
+            level: LogLevel::default(),
+        }
+    }
+}
+
+fn database_enabled_default() -> bool {
+    true
+}
+
+fn access_log_default() -> bool {
+    true
+}
+
+fn flag_enabled_default() -> bool {
+    true
 }
 
 impl ServiceKind {
@@ -76,6 +565,197 @@ impl ServiceKind {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     pub fn test_service_info_new() {}
+
+    #[test]
+    pub fn test_service_definition_database_section() {
+        let definition: ServiceDefinition = toml::from_str(
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "grpc"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(definition.info.name, "example");
+        assert!(definition.database.enabled);
+
+        let definition: ServiceDefinition = toml::from_str(
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "grpc"
+
+            [database]
+            enabled = false
+        "#,
+        )
+        .unwrap();
+
+        assert!(!definition.database.enabled);
+    }
+
+    #[test]
+    pub fn test_service_definition_http_section() {
+        let definition: ServiceDefinition = toml::from_str(
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "http"
+
+            [http]
+            address = "127.0.0.1"
+            workers = 4
+
+            [http.limits]
+            json = "2MiB"
+
+            [http.tls]
+            cert = "certs/cert.pem"
+            key = "certs/key.pem"
+
+            [http.cors]
+            origins = ["https://example.com"]
+            credentials = true
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(definition.http.address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(definition.http.workers, Some(4));
+        assert_eq!(definition.http.keep_alive, None);
+        assert_eq!(
+            definition.http.limits.get("json").map(|s| s.as_str()),
+            Some("2MiB")
+        );
+        assert_eq!(definition.http.tls.unwrap().key, "certs/key.pem");
+        assert_eq!(definition.http.cors.origins, vec!["https://example.com"]);
+        assert_eq!(definition.http.cors.credentials, Some(true));
+        assert_eq!(definition.http.cors.methods, None);
+    }
+
+    #[test]
+    pub fn test_service_definition_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let path = dir.join("service.toml");
+        std::fs::write(
+            &path,
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "http"
+
+            [http]
+            address = "127.0.0.1"
+            workers = 4
+        "#,
+        )
+        .unwrap();
+
+        std::fs::write(
+            dir.join("service.prod.toml"),
+            r#"
+            [http]
+            workers = 16
+        "#,
+        )
+        .unwrap();
+
+        let base = ServiceDefinition::new(Some(&path), None).unwrap();
+        assert_eq!(base.http.workers, Some(4));
+        assert_eq!(base.profile, None);
+
+        let definition = ServiceDefinition::new(Some(&path), Some("prod")).unwrap();
+        assert_eq!(definition.restart_keys(&base.content), vec!["http"]);
+        assert!(definition.restart_keys(&definition.content).is_empty());
+        assert_eq!(definition.info.name, "example");
+        assert_eq!(definition.http.address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(definition.http.workers, Some(16));
+        assert_eq!(definition.profile.as_deref(), Some("prod"));
+        assert_eq!(
+            definition.files(),
+            vec![path.clone(), dir.join("service.prod.toml")]
+        );
+
+        assert!(ServiceDefinition::new(Some(&path), Some("staging")).is_err());
+        assert!(ServiceDefinition::new(Some(&path), Some("../prod")).is_err());
+    }
+
+    #[test]
+    pub fn test_service_definition_manifest() {
+        let manifest = r#"
+            name = "example"
+            version = "0.1.0"
+            type = "pubsub"
+
+            [server]
+            port = 8080
+            address = "::"
+            timeout = 10
+
+            [database]
+            name = "examples"
+            host = "mongo"
+            password = "secret:file:///run/secrets/db"
+            connect_timeout_ms = 5000
+
+            [logging]
+            access_log = false
+            level = "warn"
+
+            [logging.fields]
+            region = "us-east-1"
+
+            [clients.payments]
+            address = "http://payments:9090"
+            timeout = 5
+
+            [pubsub]
+            topics = ["examples.created"]
+            subscriptions = ["orders.created"]
+        "#;
+
+        let definition: ServiceDefinition = toml::from_str(manifest).unwrap();
+        assert!(definition.validate().is_ok());
+        assert_eq!(definition.server.port, Some(8080));
+        assert_eq!(definition.server.address.as_deref(), Some("::"));
+        assert_eq!(definition.database.name.as_deref(), Some("examples"));
+        assert_eq!(definition.database.connect_timeout_ms, Some(5000));
+        assert!(!definition.logging.access_log);
+        assert_eq!(definition.logging.level, LogLevel::Warn);
+        assert_eq!(definition.logging.fields["region"], "us-east-1");
+        assert_eq!(definition.clients["payments"].timeout, Some(5));
+        assert_eq!(definition.pubsub.topics, vec!["examples.created"]);
+
+        let invalid = |from: &str, to: &str| {
+            let definition: ServiceDefinition =
+                toml::from_str(&manifest.replace(from, to)).unwrap();
+            definition.validate().is_err()
+        };
+
+        assert!(invalid("port = 8080", "port = 70000"));
+        assert!(invalid(r#"address = "::""#, r#"address = "localhost""#));
+        assert!(invalid("timeout = 10", "timeout = 0"));
+        assert!(invalid("http://payments:9090", "payments"));
+        assert!(invalid(
+            r#"["examples.created"]"#,
+            r#"["examples created"]"#
+        ));
+        assert!(invalid(
+            r#"topics = ["examples.created"]
+            subscriptions = ["orders.created"]"#,
+            ""
+        ));
+
+        let definition: ServiceDefinition =
+            toml::from_str(&manifest.replace(r#"type = "pubsub""#, r#"type = "kind""#)).unwrap();
+        let keys: Vec<String> = definition.problems().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["type"]);
+    }
 }
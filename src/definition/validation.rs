@@ -1,4 +1,6 @@
-use validator::ValidationError;
+use std::collections::HashMap;
+
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
 
 pub fn service_kind_oneof(value: &str) -> Result<(), ValidationError> {
     let supported_services = vec!["grpc", "http", "pubsub"];
@@ -10,6 +12,105 @@ pub fn service_kind_oneof(value: &str) -> Result<(), ValidationError> {
     Err(ValidationError::new("value is not supported"))
 }
 
+/// Bind addresses are IP addresses, with or without brackets, or Unix
+/// domain socket paths, prefixed by `unix:`.
+pub fn bind_address(value: &str) -> Result<(), ValidationError> {
+    if let Some(path) = value.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err(ValidationError::new("value has no socket path"));
+        }
+
+        return Ok(());
+    }
+
+    let ip = value.trim_start_matches('[').trim_end_matches(']');
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("value is not an IP address")),
+    }
+}
+
+/// Topic names have letters, digits, '.', '-' or '_'.
+pub fn topic_names(value: &[String]) -> Result<(), ValidationError> {
+    let valid = |name: &String| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ['.', '-', '_'].contains(&c))
+    };
+
+    if value.iter().all(valid) {
+        return Ok(());
+    }
+
+    Err(ValidationError::new("value is not a valid topic name"))
+}
+
+/// Method timeouts are keyed by a method or a service name, and last at least
+/// one second.
+pub fn method_timeouts(value: &HashMap<String, u64>) -> Result<(), ValidationError> {
+    let valid = |(name, timeout): (&String, &u64)| {
+        !name.trim_start_matches('/').is_empty() && !name.ends_with('/') && *timeout >= 1
+    };
+
+    if value.iter().all(valid) {
+        return Ok(());
+    }
+
+    Err(ValidationError::new("value is not a valid method timeout"))
+}
+
+/// Flattens validation errors into pairs of key, prefixed by `prefix`, and
+/// problem, sorted by key.
+pub fn problems(prefix: &str, errors: &ValidationErrors) -> Vec<(String, String)> {
+    let mut problems = Vec::new();
+
+    for (field, kind) in errors.errors() {
+        let key = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                problems.extend(errors.iter().map(|e| (key.clone(), describe(e))));
+            }
+            ValidationErrorsKind::Struct(errors) => problems.extend(self::problems(&key, errors)),
+            ValidationErrorsKind::List(list) => {
+                for (index, errors) in list {
+                    problems.extend(self::problems(&format!("{}[{}]", key, index), errors));
+                }
+            }
+        }
+    }
+
+    problems.sort();
+    problems
+}
+
+/// Describes an error by its message or, when it has none, by its code and
+/// parameters, like `range (max = 100, min = 1)`.
+fn describe(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let mut params: Vec<String> = error
+        .params
+        .iter()
+        .filter(|(name, _)| *name != "value")
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect();
+
+    if params.is_empty() {
+        return error.code.to_string();
+    }
+
+    params.sort();
+    format!("{} ({})", error.code, params.join(", "))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
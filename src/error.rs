@@ -6,6 +6,12 @@ pub enum Error {
     DefinitionParser(String),
     UnsupportedSetting(String),
     NotFound,
+    DatabaseSettings(String),
+    DatabaseConnection(String),
+    HttpSettings(String),
+    ServerSettings(String),
+    Config(String),
+    Secret(String),
 }
 
 impl Error {
@@ -17,6 +23,12 @@ impl Error {
             }
             Error::UnsupportedSetting(s) => format!("unsupported toml setting '{}'", s),
             Error::NotFound => format!("not found"),
+            Error::DatabaseSettings(s) => format!("invalid database settings '{}'", s),
+            Error::DatabaseConnection(s) => format!("could not connect to database '{}'", s),
+            Error::HttpSettings(s) => format!("invalid HTTP settings '{}'", s),
+            Error::ServerSettings(s) => format!("invalid server settings '{}'", s),
+            Error::Config(s) => format!("invalid service config '{}'", s),
+            Error::Secret(s) => format!("could not load secret '{}'", s),
         }
     }
 }
@@ -0,0 +1,326 @@
+// Feature flags, declared in the `[flags]` section of the settings file and
+// overridable by FLAG_ prefixed environment variables. Flags can be rolled
+// out to a percentage of the requests, bucketed by one of their attributes.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::definition::FlagDefinition;
+use crate::error::{Error, Result};
+
+const ENV_PREFIX: &str = "FLAG_";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flag {
+    pub enabled: bool,
+
+    /// Percentage, from 0 to 100, of the contexts the flag is enabled for.
+    pub rollout: Option<u8>,
+
+    /// The context attribute the rollout is bucketed by, like `user_id`.
+    pub key: Option<String>,
+}
+
+/// The feature flags of a service.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Flags {
+    flags: HashMap<String, Flag>,
+}
+
+/// Attributes of what a flag is evaluated for, like the user of a request.
+#[derive(Debug, Clone, Default)]
+pub struct FlagContext {
+    attributes: HashMap<String, String>,
+}
+
+impl Flags {
+    /// Loads and validates the flags. For a flag named `new-ui`, the
+    /// FLAG_NEW_UI and FLAG_NEW_UI_ROLLOUT environment variables take
+    /// precedence over its `enabled` and `rollout` settings, and must be
+    /// valid when they are set.
+    pub(crate) fn new(definitions: &HashMap<String, FlagDefinition>) -> Result<Self> {
+        // Names like `new-ui` and `new_ui` would share their variables.
+        let mut names: Vec<&String> = definitions.keys().collect();
+        names.sort();
+
+        let mut envs: HashMap<String, &String> = HashMap::new();
+        for name in names {
+            if let Some(other) = envs.insert(env_name(name), name) {
+                return Err(Error::UnsupportedSetting(format!(
+                    "flags '{}' and '{}' use the same environment variable {}",
+                    other,
+                    name,
+                    env_name(name)
+                )));
+            }
+        }
+
+        let mut flags = HashMap::new();
+
+        for (name, definition) in definitions {
+            let (enabled, rollout, key) = match definition {
+                FlagDefinition::Enabled(enabled) => (*enabled, None, None),
+                FlagDefinition::Rollout {
+                    enabled,
+                    rollout,
+                    key,
+                } => (*enabled, *rollout, key.clone()),
+            };
+
+            let (enabled_env, rollout_env) = env_names(name);
+            let flag = Flag {
+                enabled: env_value(&enabled_env, "a boolean")
+                    .map_err(|e| invalid(name, &format!("{}: {}", enabled_env, e)))?
+                    .unwrap_or(enabled),
+                rollout: env_value(&rollout_env, "a percentage")
+                    .map_err(|e| invalid(name, &format!("{}: {}", rollout_env, e)))?
+                    .or(rollout),
+                key,
+            };
+
+            match flag.rollout {
+                Some(rollout) if rollout > 100 => {
+                    return Err(invalid(name, "rollout must be a percentage, up to 100"));
+                }
+                Some(_) if flag.key.is_none() => {
+                    return Err(invalid(name, "rollout needs a key"));
+                }
+                _ => {}
+            }
+
+            flags.insert(name.clone(), flag);
+        }
+
+        Ok(Flags { flags })
+    }
+
+    /// Tells if a flag is enabled for a context. Unknown flags are
+    /// disabled, as well as rolled out flags for contexts without their key
+    /// attribute.
+    ///
+    /// ```ignore
+    /// if service.flags().enabled("new-search", FlagContext::default().with_attribute("user_id", &id)) {
+    ///     ...
+    /// }
+    /// ```
+    pub fn enabled(&self, name: &str, context: &FlagContext) -> bool {
+        let flag = match self.flags.get(name) {
+            Some(flag) if flag.enabled => flag,
+            _ => return false,
+        };
+
+        let rollout = match flag.rollout {
+            None | Some(100..=u8::MAX) => return true,
+            Some(rollout) => rollout,
+        };
+
+        let value = match flag.key.as_ref().and_then(|k| context.attributes.get(k)) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        bucket(name, value) < rollout as u64
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Flag> {
+        self.flags.get(name)
+    }
+
+    /// Gives back the names of all flags.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.flags.keys().map(|n| n.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl FlagContext {
+    /// Builds a context from the metadata of a gRPC request, with an
+    /// attribute for every text entry, like `x-user-id`.
+    pub fn from_request<B>(request: &tonic::Request<B>) -> Self {
+        let mut context = FlagContext::default();
+
+        for entry in request.metadata().iter() {
+            if let tonic::metadata::KeyAndValueRef::Ascii(key, value) = entry {
+                if let Ok(value) = value.to_str() {
+                    context.with_attribute(key.as_str(), value);
+                }
+            }
+        }
+
+        context
+    }
+
+    pub fn with_attribute(&mut self, name: &str, value: &str) -> &mut Self {
+        self.attributes.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Places a context in one of 100 buckets, always the same for a flag and
+/// an attribute value, using the 64 bits FNV-1a hash.
+fn bucket(name: &str, value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in name.bytes().chain([b':']).chain(value.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash % 100
+}
+
+fn env_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}{}", ENV_PREFIX, name)
+}
+
+/// Gives back the environment variables of the flags that are set but can
+/// not be parsed, with their problems.
+pub(crate) fn env_problems(definitions: &HashMap<String, FlagDefinition>) -> Vec<(String, String)> {
+    let mut names: Vec<&String> = definitions.keys().collect();
+    names.sort();
+
+    let mut problems = Vec::new();
+    for name in names {
+        let (enabled_env, rollout_env) = env_names(name);
+        if let Err(e) = env_value::<bool>(&enabled_env, "a boolean") {
+            problems.push((enabled_env, e));
+        }
+
+        if let Err(e) = env_value::<u8>(&rollout_env, "a percentage") {
+            problems.push((rollout_env, e));
+        }
+    }
+
+    problems
+}
+
+/// Gives back the environment variables overriding the `enabled` and
+/// `rollout` settings of a flag.
+fn env_names(name: &str) -> (String, String) {
+    let env = env_name(name);
+    let rollout = format!("{}_ROLLOUT", env);
+    (env, rollout)
+}
+
+/// Reads an environment variable, failing when it is set but can not be
+/// parsed.
+fn env_value<T: FromStr>(key: &str, expected: &str) -> std::result::Result<Option<T>, String> {
+    match std::env::var(key) {
+        Err(_) => Ok(None),
+        Ok(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(format!("'{}' is not {}", value, expected)),
+        },
+    }
+}
+
+fn invalid(name: &str, message: &str) -> Error {
+    Error::UnsupportedSetting(format!("flag '{}': {}", name, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definitions(content: &str) -> HashMap<String, FlagDefinition> {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    pub fn test_flags() {
+        let flags = Flags::new(&definitions(
+            r#"
+            search = true
+            legacy = false
+            test-flags-env = false
+
+            [checkout]
+            enabled = true
+            rollout = 30
+            key = "user_id"
+        "#,
+        ))
+        .unwrap();
+
+        let empty = FlagContext::default();
+        assert!(flags.enabled("search", &empty));
+        assert!(!flags.enabled("legacy", &empty));
+        assert!(!flags.enabled("unknown", &empty));
+        assert!(!flags.enabled("checkout", &empty));
+        assert_eq!(
+            flags.names(),
+            vec!["checkout", "legacy", "search", "test-flags-env"]
+        );
+
+        let enabled = (0..1000)
+            .filter(|id| {
+                flags.enabled(
+                    "checkout",
+                    FlagContext::default().with_attribute("user_id", &id.to_string()),
+                )
+            })
+            .count();
+        assert!((250..350).contains(&enabled));
+
+        let mut context = FlagContext::default();
+        context.with_attribute("user_id", "42");
+        let first = flags.enabled("checkout", &context);
+        assert!((0..10).all(|_| flags.enabled("checkout", &context) == first));
+
+        let mut env = crate::testing::EnvGuard::new();
+        env.set("FLAG_TEST_FLAGS_ENV", "true");
+        let flags = Flags::new(&definitions("test-flags-env = false")).unwrap();
+        assert!(flags.enabled("test-flags-env", &empty));
+
+        env.set("FLAG_TEST_FLAGS_ENV", "yes");
+        let invalid = definitions("test-flags-env = false");
+        assert!(matches!(
+            Flags::new(&invalid),
+            Err(Error::UnsupportedSetting(_))
+        ));
+        assert_eq!(
+            env_problems(&invalid),
+            vec![(
+                "FLAG_TEST_FLAGS_ENV".to_string(),
+                "'yes' is not a boolean".to_string()
+            )]
+        );
+        drop(env);
+
+        assert!(Flags::new(&definitions(
+            "a = { enabled = true, rollout = 101, key = \"id\" }"
+        ))
+        .is_err());
+        assert!(Flags::new(&definitions("a = { enabled = true, rollout = 10 }")).is_err());
+        assert!(matches!(
+            Flags::new(&definitions("new-ui = true\nnew_ui = false")),
+            Err(Error::UnsupportedSetting(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_flag_context_from_request() {
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert("x-user-id", "42".parse().unwrap());
+
+        let context = FlagContext::from_request(&request);
+        assert_eq!(
+            context.attributes.get("x-user-id").map(|v| v.as_str()),
+            Some("42")
+        );
+    }
+}
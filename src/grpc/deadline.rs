@@ -0,0 +1,289 @@
+// Request deadlines of gRPC services. Every request gets the timeout of its
+// method, from the settings, shortened by the grpc-timeout header of the
+// client, and what remains of it can be propagated to the calls made to
+// other services while handling the request.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use http::{HeaderMap, Request, Response};
+use tonic::body::BoxBody;
+use tonic::transport::Body;
+use tower::{Layer, Service};
+
+pub(crate) const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+tokio::task_local! {
+    static DEADLINE: Deadline;
+}
+
+/// When a request must be answered by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline {
+    instant: Instant,
+}
+
+impl Deadline {
+    /// Gives back the deadline of the request being handled, when called
+    /// from its RPC method (but not from tasks spawned by it).
+    pub fn current() -> Option<Deadline> {
+        DEADLINE.try_with(|deadline| *deadline).ok()
+    }
+
+    /// Retrieves the deadline from RPC's request argument.
+    pub fn from_request<B>(request: &tonic::Request<B>) -> Option<Deadline> {
+        request.extensions().get::<Deadline>().copied()
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// Gives back how long until the deadline, zero when it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.instant.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Duration::ZERO
+    }
+}
+
+/// The request timeouts of a service, by method.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Timeouts {
+    default: Duration,
+    methods: HashMap<String, Duration>,
+}
+
+impl Timeouts {
+    /// Creates the timeouts, with `methods` keyed by method, like
+    /// `examples.Examples/Search`, or by service, like `examples.Examples`.
+    pub fn new(default: Duration, methods: &HashMap<String, Duration>) -> Self {
+        Timeouts {
+            default,
+            methods: methods
+                .iter()
+                .map(|(name, timeout)| (name.trim_start_matches('/').to_string(), *timeout))
+                .collect(),
+        }
+    }
+
+    /// Gives back the timeout of a request path, like
+    /// `/examples.Examples/Search`, the one of its method, of its service or
+    /// the default one.
+    pub fn get(&self, path: &str) -> Duration {
+        let method = path.trim_start_matches('/');
+        let service = method.split('/').next().unwrap_or_default();
+
+        self.methods
+            .get(method)
+            .or_else(|| self.methods.get(service))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Answers requests taking longer than their timeouts with a
+/// DEADLINE_EXCEEDED status, and gives RPC methods access to their
+/// deadlines.
+#[derive(Debug, Clone)]
+pub(crate) struct DeadlineLayer {
+    timeouts: Arc<Timeouts>,
+}
+
+impl DeadlineLayer {
+    pub(crate) fn new(timeouts: &Timeouts) -> Self {
+        DeadlineLayer {
+            timeouts: Arc::new(timeouts.clone()),
+        }
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        DeadlineService {
+            inner: service,
+            timeouts: self.timeouts.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DeadlineService<S> {
+    inner: S,
+    timeouts: Arc<Timeouts>,
+}
+
+impl<S> Service<Request<Body>> for DeadlineService<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        futures::future::BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // See the GrpcMiddleware on why the inner service is replaced.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let mut timeout = self.timeouts.get(request.uri().path());
+        if let Some(client) = grpc_timeout(request.headers()) {
+            timeout = timeout.min(client);
+        }
+
+        let deadline = Deadline {
+            instant: Instant::now() + timeout,
+        };
+        request.extensions_mut().insert(deadline);
+
+        Box::pin(DEADLINE.scope(deadline, async move {
+            match tokio::time::timeout(timeout, inner.call(request)).await {
+                Ok(response) => response,
+                Err(_) => {
+                    Ok(tonic::Status::deadline_exceeded("request deadline exceeded").to_http())
+                }
+            }
+        }))
+    }
+}
+
+fn grpc_timeout(headers: &HeaderMap) -> Option<Duration> {
+    parse_grpc_timeout(headers.get(GRPC_TIMEOUT)?.to_str().ok()?)
+}
+
+/// Parses a grpc-timeout value, an integer of up to 8 digits followed by its
+/// unit, like `100m` for 100 milliseconds.
+pub(crate) fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_timeouts() {
+        let methods = HashMap::from([
+            ("/examples.Examples".to_string(), Duration::from_secs(10)),
+            (
+                "examples.Examples/Search".to_string(),
+                Duration::from_secs(60),
+            ),
+        ]);
+        let timeouts = Timeouts::new(Duration::from_secs(30), &methods);
+
+        assert_eq!(
+            timeouts.get("/examples.Examples/Search"),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            timeouts.get("/examples.Examples/Get"),
+            Duration::from_secs(10)
+        );
+        assert_eq!(timeouts.get("/other.Other/Get"), Duration::from_secs(30));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(grpc_timeout(&headers), None);
+        headers.insert(GRPC_TIMEOUT, "100m".parse().unwrap());
+        assert_eq!(grpc_timeout(&headers), Some(Duration::from_millis(100)));
+        headers.insert(GRPC_TIMEOUT, "2S".parse().unwrap());
+        assert_eq!(grpc_timeout(&headers), Some(Duration::from_secs(2)));
+        headers.insert(GRPC_TIMEOUT, "123456789S".parse().unwrap());
+        assert_eq!(grpc_timeout(&headers), None);
+        headers.insert(GRPC_TIMEOUT, "10x".parse().unwrap());
+        assert_eq!(grpc_timeout(&headers), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_deadline_layer() {
+        let methods = HashMap::from([(
+            "examples.Examples/Slow".to_string(),
+            Duration::from_millis(50),
+        )]);
+        let layer = DeadlineLayer::new(&Timeouts::new(Duration::from_secs(30), &methods));
+
+        let mut service = layer.layer(tower::service_fn(|request: Request<Body>| async move {
+            let deadline = request.extensions().get::<Deadline>().copied();
+            assert_eq!(Deadline::current(), deadline);
+
+            let outgoing = crate::grpc::Client::propagate_deadline(tonic::Request::new(()));
+            assert!(outgoing.unwrap().metadata().get(GRPC_TIMEOUT).is_some());
+
+            // A shorter timeout of the outgoing request is kept.
+            let mut outgoing = tonic::Request::new(());
+            outgoing.set_timeout(Duration::from_millis(10));
+            let outgoing = crate::grpc::Client::propagate_deadline(outgoing).unwrap();
+            let timeout = outgoing.metadata().get(GRPC_TIMEOUT).unwrap();
+            assert_eq!(
+                parse_grpc_timeout(timeout.to_str().unwrap()),
+                Some(Duration::from_millis(10))
+            );
+
+            if request.uri().path().ends_with("Slow") {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+
+            let remaining = deadline.unwrap().remaining().as_millis().to_string();
+            let mut response = Response::new(tonic::body::empty_body());
+            response
+                .headers_mut()
+                .insert("x-remaining", remaining.parse().unwrap());
+
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+
+        let request = |path: &str, timeout: Option<&str>| {
+            let mut request = Request::builder().uri(path);
+            if let Some(timeout) = timeout {
+                request = request.header(GRPC_TIMEOUT, timeout);
+            }
+
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = service
+            .call(request("/examples.Examples/Slow", None))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["grpc-status"], "4");
+
+        let response = service
+            .call(request("/examples.Examples/Get", Some("1S")))
+            .await
+            .unwrap();
+        let remaining: u64 = response.headers()["x-remaining"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(remaining > 900 && remaining <= 1000);
+    }
+}
@@ -0,0 +1,292 @@
+// Structured error details, following the google.rpc error model. They are
+// sent inside the grpc-status-details-bin trailer as an encoded
+// google.rpc.Status message.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use prost::Message;
+
+const BAD_REQUEST_TYPE: &str = "type.googleapis.com/google.rpc.BadRequest";
+const ERROR_INFO_TYPE: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+const RETRY_INFO_TYPE: &str = "type.googleapis.com/google.rpc.RetryInfo";
+const LOCALIZED_MESSAGE_TYPE: &str = "type.googleapis.com/google.rpc.LocalizedMessage";
+
+/// google.rpc.Status
+#[derive(Clone, PartialEq, Message)]
+pub struct Status {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<prost_types::Any>,
+}
+
+/// google.rpc.BadRequest
+#[derive(Clone, PartialEq, Message)]
+pub struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub field_violations: Vec<FieldViolation>,
+}
+
+/// google.rpc.BadRequest.FieldViolation
+#[derive(Clone, PartialEq, Message)]
+pub struct FieldViolation {
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// google.rpc.ErrorInfo
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// google.rpc.RetryInfo
+#[derive(Clone, PartialEq, Message)]
+pub struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    pub retry_delay: Option<prost_types::Duration>,
+}
+
+/// google.rpc.LocalizedMessage
+#[derive(Clone, PartialEq, Message)]
+pub struct LocalizedMessage {
+    #[prost(string, tag = "1")]
+    pub locale: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+/// The detail messages attached to an RPC error.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorDetails {
+    pub bad_request: Option<BadRequest>,
+    pub error_info: Option<ErrorInfo>,
+    pub retry_info: Option<RetryInfo>,
+    pub localized_message: Option<LocalizedMessage>,
+}
+
+impl ErrorDetails {
+    /// Decodes the details carried by a status received from a gRPC call.
+    /// Unknown detail messages are ignored.
+    pub fn from_status(status: &tonic::Status) -> Self {
+        let mut details = ErrorDetails::default();
+        let encoded = match Status::decode(status.details()) {
+            Ok(s) => s,
+            Err(_) => return details,
+        };
+
+        for any in encoded.details {
+            let value = any.value.as_slice();
+            match any.type_url.as_str() {
+                BAD_REQUEST_TYPE => details.bad_request = BadRequest::decode(value).ok(),
+                ERROR_INFO_TYPE => details.error_info = ErrorInfo::decode(value).ok(),
+                RETRY_INFO_TYPE => details.retry_info = RetryInfo::decode(value).ok(),
+                LOCALIZED_MESSAGE_TYPE => {
+                    details.localized_message = LocalizedMessage::decode(value).ok()
+                }
+                _ => {}
+            }
+        }
+
+        details
+    }
+
+    /// Gives back the field violations, if any.
+    pub fn field_violations(&self) -> &[FieldViolation] {
+        match &self.bad_request {
+            Some(b) => &b.field_violations,
+            None => &[],
+        }
+    }
+
+    /// Gives back the delay suggested by the server before retrying.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        let delay = self.retry_info.as_ref()?.retry_delay.as_ref()?;
+        Some(Duration::new(
+            delay.seconds.max(0) as u64,
+            delay.nanos.max(0) as u32,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bad_request.is_none()
+            && self.error_info.is_none()
+            && self.retry_info.is_none()
+            && self.localized_message.is_none()
+    }
+
+    pub(crate) fn add_field_violation(&mut self, field: &str, description: &str) {
+        self.bad_request
+            .get_or_insert_with(BadRequest::default)
+            .field_violations
+            .push(FieldViolation {
+                field: field.to_string(),
+                description: description.to_string(),
+            });
+    }
+
+    /// Encodes the details as a google.rpc.Status message.
+    pub(crate) fn encode(&self, code: tonic::Code, message: &str) -> Vec<u8> {
+        let mut details = Vec::new();
+
+        if let Some(d) = &self.bad_request {
+            details.push(to_any(BAD_REQUEST_TYPE, d));
+        }
+        if let Some(d) = &self.error_info {
+            details.push(to_any(ERROR_INFO_TYPE, d));
+        }
+        if let Some(d) = &self.retry_info {
+            details.push(to_any(RETRY_INFO_TYPE, d));
+        }
+        if let Some(d) = &self.localized_message {
+            details.push(to_any(LOCALIZED_MESSAGE_TYPE, d));
+        }
+
+        Status {
+            code: code as i32,
+            message: message.to_string(),
+            details,
+        }
+        .encode_to_vec()
+    }
+}
+
+fn to_any<M: Message>(type_url: &str, message: &M) -> prost_types::Any {
+    prost_types::Any {
+        type_url: type_url.to_string(),
+        value: message.encode_to_vec(),
+    }
+}
+
+/// Converts validation errors into field violations, using dotted paths for
+/// nested structures and indexes for lists, e.g. `items[0].name`.
+pub(crate) fn field_violations(errors: &validator::ValidationErrors) -> Vec<FieldViolation> {
+    let mut violations = Vec::new();
+    collect_violations("", errors, &mut violations);
+    violations.sort_by(|a, b| a.field.cmp(&b.field));
+    violations
+}
+
+fn collect_violations(
+    prefix: &str,
+    errors: &validator::ValidationErrors,
+    violations: &mut Vec<FieldViolation>,
+) {
+    use validator::ValidationErrorsKind;
+
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                for e in errors {
+                    violations.push(FieldViolation {
+                        field: path.clone(),
+                        description: match &e.message {
+                            Some(m) => m.to_string(),
+                            None => e.code.to_string(),
+                        },
+                    });
+                }
+            }
+            ValidationErrorsKind::Struct(inner) => collect_violations(&path, inner, violations),
+            ValidationErrorsKind::List(items) => {
+                for (index, inner) in items {
+                    collect_violations(&format!("{}[{}]", path, index), inner, violations);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_error_details_round_trip() {
+        let mut details = ErrorDetails {
+            error_info: Some(ErrorInfo {
+                reason: "EXAMPLE_LOCKED".to_string(),
+                domain: "example.service.local".to_string(),
+                metadata: HashMap::new(),
+            }),
+            retry_info: Some(RetryInfo {
+                retry_delay: Some(prost_types::Duration {
+                    seconds: 2,
+                    nanos: 0,
+                }),
+            }),
+            ..ErrorDetails::default()
+        };
+
+        details.add_field_violation("name", "must not be empty");
+
+        let status = tonic::Status::with_details(
+            tonic::Code::InvalidArgument,
+            "invalid request",
+            details
+                .encode(tonic::Code::InvalidArgument, "invalid request")
+                .into(),
+        );
+
+        let decoded = ErrorDetails::from_status(&status);
+        assert_eq!(decoded, details);
+        assert_eq!(decoded.field_violations()[0].field, "name");
+        assert_eq!(decoded.retry_delay(), Some(Duration::from_secs(2)));
+        assert!(ErrorDetails::from_status(&tonic::Status::internal("")).is_empty());
+    }
+
+    #[test]
+    pub fn test_field_violations() {
+        use validator::Validate;
+
+        #[derive(Validate)]
+        struct Item {
+            #[validate(range(min = 1))]
+            quantity: i32,
+        }
+
+        #[derive(Validate)]
+        struct Order {
+            #[validate(length(min = 1, message = "must not be empty"))]
+            name: String,
+            #[validate]
+            items: Vec<Item>,
+        }
+
+        let order = Order {
+            name: "".to_string(),
+            items: vec![Item { quantity: 1 }, Item { quantity: 0 }],
+        };
+
+        let violations = field_violations(&order.validate().unwrap_err());
+        assert_eq!(
+            violations,
+            vec![
+                FieldViolation {
+                    field: "items[1].quantity".to_string(),
+                    description: "range".to_string(),
+                },
+                FieldViolation {
+                    field: "name".to_string(),
+                    description: "must not be empty".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -1,14 +1,19 @@
 // We implement here a gRPC middleware to provide access for the Service
 // object inside every RPC method.
 
+pub mod deadline;
+pub mod details;
 pub mod rpc;
+pub mod tls;
+pub(crate) mod web;
 
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use tonic::service::interceptor::InterceptedService;
 use tonic::{body::BoxBody, transport::Body};
 use tower::{Layer, Service};
 
-use crate::config::{Config, GetEnv};
+use crate::config::{vars, Config, GetEnv};
 use crate::service;
 
 #[derive(Debug, Clone)]
@@ -65,6 +70,7 @@ where
         let mut inner = std::mem::replace(&mut self.inner, clone);
 
         req.extensions_mut().insert(self.service.clone());
+        tls::expose_peer(req.extensions_mut());
         Box::pin(async move {
             let response = inner.call(req).await?;
             Ok(response)
@@ -75,7 +81,7 @@ where
 /// A gRPC client connection container. It uses a tokio::sync::Mutex inside to
 /// give a &mut for the inner data.
 ///
-/// ```
+/// ```ignore
 /// struct Server {
 ///     foo: ClientConnection<FooServiceClient<Channel>>,
 /// }
@@ -88,6 +94,12 @@ pub type ClientConnection<T> = tokio::sync::Mutex<T>;
 /// gRPC clients inside a server implementation, to access their APIs.
 pub type Channel = tonic::transport::Channel;
 
+/// A gRPC client channel that propagates the deadline of the request being
+/// handled to the calls made through it, see `Client::propagate_deadline`.
+pub type DeadlineChannel = InterceptedService<Channel, DeadlineInterceptor>;
+
+pub type DeadlineInterceptor = fn(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status>;
+
 /// Options to customize the connection URL with a gRPC service.
 pub struct ClientOptions {
     pub hostname: String,
@@ -102,7 +114,10 @@ impl Client {
     pub fn url(service_name: &str) -> String {
         let host =
             Config::get_os_env("SERVICES_HOSTNAME", Some("service.local".to_string())).unwrap();
-        let port = Config::get_os_env("SERVICES_GRPC_PORT", Some(service::builder::SERVICE_PORT)).unwrap();
+        let port = vars::SERVICES_GRPC_PORT
+            .get(None)
+            .map(i64::from)
+            .unwrap_or(service::builder::SERVICE_PORT);
         format!("http://{}.{}:{}", service_name, host, port)
     }
 
@@ -117,6 +132,43 @@ impl Client {
         format!("{}:{}", host, options.port)
     }
 
+    /// An interceptor for gRPC clients, sending what remains of the deadline
+    /// of the request being handled as their grpc-timeout, unless the call
+    /// sets a shorter one. Calls are not made when it has already passed.
+    /// Channels given by `Service::client_channel` already use it.
+    ///
+    /// ```ignore
+    /// let client = FooServiceClient::with_interceptor(channel, Client::propagate_deadline);
+    /// ```
+    pub fn propagate_deadline(
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(deadline) = deadline::Deadline::current() {
+            if deadline.is_expired() {
+                return Err(tonic::Status::deadline_exceeded(
+                    "request deadline exceeded",
+                ));
+            }
+
+            let current = request
+                .metadata()
+                .get(deadline::GRPC_TIMEOUT)
+                .and_then(|t| t.to_str().ok())
+                .and_then(deadline::parse_grpc_timeout);
+
+            let remaining = deadline.remaining();
+            request.set_timeout(current.map_or(remaining, |c| c.min(remaining)));
+        }
+
+        Ok(request)
+    }
+
+    /// Wraps a channel so that its calls propagate the deadline of the
+    /// request being handled.
+    pub fn with_deadline(channel: Channel) -> DeadlineChannel {
+        InterceptedService::new(channel, Client::propagate_deadline)
+    }
+
     /// Creates a gRPC connection container to be used with another gRPC service.
     pub fn new_connection<T>(data: T) -> ClientConnection<T> {
         tokio::sync::Mutex::new(data)
@@ -1,13 +1,25 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::grpc::details::{self, ErrorDetails, ErrorInfo, LocalizedMessage, RetryInfo};
+
 /// Response is an alias for RPC's methods result type.
 pub type Response<B> = std::result::Result<tonic::Response<B>, tonic::Status>;
 
 /// Request is an alias for RPC's methods request argument type.
 pub type Request<B> = tonic::Request<B>;
 
+/// An RPC error, carrying one of the canonical gRPC status codes.
+///
+/// Errors from pocket, the database driver and the validator can be
+/// converted into it, so helper functions returning `Result<T, rpc::Error>`
+/// can use `?` on them. It also converts into `tonic::Status`, letting
+/// handlers use `?` on the helpers results.
 #[derive(Debug)]
 pub struct Error {
     code: ErrorCode,
     message: Option<String>,
+    details: ErrorDetails,
 }
 
 impl std::error::Error for Error {}
@@ -25,31 +37,181 @@ impl std::fmt::Display for Error {
 }
 
 impl Error {
-    pub(crate) fn new(code: ErrorCode, msg: Option<&str>) -> Self {
+    /// Creates a new RPC error with an optional message.
+    pub fn new(code: ErrorCode, msg: Option<&str>) -> Self {
         Error {
             code,
             message: msg.map(|s| s.to_string()),
+            details: ErrorDetails::default(),
         }
     }
 
-    pub(crate) fn to_status(&self) -> tonic::Status {
-        let code = match self.code {
-            ErrorCode::Validation => tonic::Code::InvalidArgument,
-            ErrorCode::Internal => tonic::Code::Internal,
-            ErrorCode::NotFound => tonic::Code::NotFound,
-            ErrorCode::Precondition => tonic::Code::FailedPrecondition,
+    /// Adds a field violation, sent as a google.rpc.BadRequest detail.
+    pub fn with_field_violation(mut self, field: &str, description: &str) -> Self {
+        self.details.add_field_violation(field, description);
+        self
+    }
+
+    /// Adds the error reason and domain, sent as a google.rpc.ErrorInfo
+    /// detail.
+    pub fn with_error_info(
+        mut self,
+        reason: &str,
+        domain: &str,
+        metadata: HashMap<String, String>,
+    ) -> Self {
+        self.details.error_info = Some(ErrorInfo {
+            reason: reason.to_string(),
+            domain: domain.to_string(),
+            metadata,
+        });
+        self
+    }
+
+    /// Tells clients how long to wait before retrying, sent as a
+    /// google.rpc.RetryInfo detail.
+    pub fn with_retry_delay(mut self, delay: Duration) -> Self {
+        self.details.retry_info = Some(RetryInfo {
+            retry_delay: Some(prost_types::Duration {
+                seconds: delay.as_secs() as i64,
+                nanos: delay.subsec_nanos() as i32,
+            }),
+        });
+        self
+    }
+
+    /// Adds a message that can be shown to end users, sent as a
+    /// google.rpc.LocalizedMessage detail.
+    pub fn with_localized_message(mut self, locale: &str, message: &str) -> Self {
+        self.details.localized_message = Some(LocalizedMessage {
+            locale: locale.to_string(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// Gives back the error detail messages.
+    pub fn details(&self) -> &ErrorDetails {
+        &self.details
+    }
+
+    /// Gives back the error code.
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// Gives back the error message, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Converts the error into a gRPC status.
+    pub fn to_status(&self) -> tonic::Status {
+        let code = self.code.into();
+        let message = self.message.clone().unwrap_or_default();
+
+        if self.details.is_empty() {
+            return tonic::Status::new(code, message);
+        }
+
+        let details = self.details.encode(code, &message);
+        tonic::Status::with_details(code, message, details.into())
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(error: Error) -> Self {
+        error.to_status()
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::new(status.code().into(), Some(status.message()))
+    }
+}
+
+impl From<crate::error::Error> for Error {
+    fn from(error: crate::error::Error) -> Self {
+        use crate::error::Error as E;
+
+        let code = match &error {
+            E::NotFound => ErrorCode::NotFound,
+            E::DatabaseConnection(_) => ErrorCode::Unavailable,
+            E::DatabaseSettings(_) | E::HttpSettings(_) | E::ServerSettings(_) | E::Config(_) => {
+                ErrorCode::Precondition
+            }
+            E::InternalOS(_) | E::DefinitionParser(_) | E::UnsupportedSetting(_) | E::Secret(_) => {
+                ErrorCode::Internal
+            }
         };
 
-        tonic::Status::new(code, self.message.clone().unwrap_or_else(|| "".to_string()))
+        Error::new(code, Some(&error.to_string()))
     }
 }
 
-#[derive(Debug)]
+impl From<crate::error::Error> for tonic::Status {
+    fn from(error: crate::error::Error) -> Self {
+        Error::from(error).to_status()
+    }
+}
+
+#[cfg(feature = "database")]
+impl From<mongodb::error::Error> for Error {
+    fn from(error: mongodb::error::Error) -> Self {
+        use mongodb::error::{ErrorKind, WriteFailure};
+
+        // The server error code for unique index violations.
+        const DUPLICATE_KEY: i32 = 11000;
+
+        let code = match error.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY => {
+                ErrorCode::AlreadyExists
+            }
+            ErrorKind::Command(e) if e.code == DUPLICATE_KEY => ErrorCode::AlreadyExists,
+            ErrorKind::InvalidArgument { .. } => ErrorCode::Validation,
+            ErrorKind::Io(_)
+            | ErrorKind::ConnectionPoolCleared { .. }
+            | ErrorKind::ServerSelection { .. }
+            | ErrorKind::DnsResolve { .. } => ErrorCode::Unavailable,
+            _ => ErrorCode::Internal,
+        };
+
+        Error::new(code, Some(&error.to_string()))
+    }
+}
+
+impl From<validator::ValidationErrors> for Error {
+    fn from(errors: validator::ValidationErrors) -> Self {
+        let mut error = Error::new(ErrorCode::Validation, Some("invalid request"));
+
+        for violation in details::field_violations(&errors) {
+            error = error.with_field_violation(&violation.field, &violation.description);
+        }
+
+        error
+    }
+}
+
+/// The canonical gRPC error codes.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ErrorCode {
     Validation,
     Internal,
     NotFound,
     Precondition,
+    Cancelled,
+    Unknown,
+    DeadlineExceeded,
+    AlreadyExists,
+    PermissionDenied,
+    ResourceExhausted,
+    Aborted,
+    OutOfRange,
+    Unimplemented,
+    Unavailable,
+    DataLoss,
+    Unauthenticated,
 }
 
 impl std::fmt::Display for ErrorCode {
@@ -59,10 +221,100 @@ impl std::fmt::Display for ErrorCode {
             ErrorCode::Internal => write!(f, "InternalError"),
             ErrorCode::NotFound => write!(f, "NotFound"),
             ErrorCode::Precondition => write!(f, "FailedPrecondition"),
+            ErrorCode::Cancelled => write!(f, "Cancelled"),
+            ErrorCode::Unknown => write!(f, "Unknown"),
+            ErrorCode::DeadlineExceeded => write!(f, "DeadlineExceeded"),
+            ErrorCode::AlreadyExists => write!(f, "AlreadyExists"),
+            ErrorCode::PermissionDenied => write!(f, "PermissionDenied"),
+            ErrorCode::ResourceExhausted => write!(f, "ResourceExhausted"),
+            ErrorCode::Aborted => write!(f, "Aborted"),
+            ErrorCode::OutOfRange => write!(f, "OutOfRange"),
+            ErrorCode::Unimplemented => write!(f, "Unimplemented"),
+            ErrorCode::Unavailable => write!(f, "Unavailable"),
+            ErrorCode::DataLoss => write!(f, "DataLoss"),
+            ErrorCode::Unauthenticated => write!(f, "Unauthenticated"),
+        }
+    }
+}
+
+impl From<ErrorCode> for tonic::Code {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Validation => tonic::Code::InvalidArgument,
+            ErrorCode::Internal => tonic::Code::Internal,
+            ErrorCode::NotFound => tonic::Code::NotFound,
+            ErrorCode::Precondition => tonic::Code::FailedPrecondition,
+            ErrorCode::Cancelled => tonic::Code::Cancelled,
+            ErrorCode::Unknown => tonic::Code::Unknown,
+            ErrorCode::DeadlineExceeded => tonic::Code::DeadlineExceeded,
+            ErrorCode::AlreadyExists => tonic::Code::AlreadyExists,
+            ErrorCode::PermissionDenied => tonic::Code::PermissionDenied,
+            ErrorCode::ResourceExhausted => tonic::Code::ResourceExhausted,
+            ErrorCode::Aborted => tonic::Code::Aborted,
+            ErrorCode::OutOfRange => tonic::Code::OutOfRange,
+            ErrorCode::Unimplemented => tonic::Code::Unimplemented,
+            ErrorCode::Unavailable => tonic::Code::Unavailable,
+            ErrorCode::DataLoss => tonic::Code::DataLoss,
+            ErrorCode::Unauthenticated => tonic::Code::Unauthenticated,
         }
     }
 }
 
+impl From<tonic::Code> for ErrorCode {
+    fn from(code: tonic::Code) -> Self {
+        match code {
+            tonic::Code::InvalidArgument => ErrorCode::Validation,
+            tonic::Code::Internal => ErrorCode::Internal,
+            tonic::Code::NotFound => ErrorCode::NotFound,
+            tonic::Code::FailedPrecondition => ErrorCode::Precondition,
+            tonic::Code::Cancelled => ErrorCode::Cancelled,
+            tonic::Code::DeadlineExceeded => ErrorCode::DeadlineExceeded,
+            tonic::Code::AlreadyExists => ErrorCode::AlreadyExists,
+            tonic::Code::PermissionDenied => ErrorCode::PermissionDenied,
+            tonic::Code::ResourceExhausted => ErrorCode::ResourceExhausted,
+            tonic::Code::Aborted => ErrorCode::Aborted,
+            tonic::Code::OutOfRange => ErrorCode::OutOfRange,
+            tonic::Code::Unimplemented => ErrorCode::Unimplemented,
+            tonic::Code::Unavailable => ErrorCode::Unavailable,
+            tonic::Code::DataLoss => ErrorCode::DataLoss,
+            tonic::Code::Unauthenticated => ErrorCode::Unauthenticated,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// Validation of incoming request messages.
+///
+/// Request types declare their rules with `validator`'s derive, usually added
+/// by `tonic_build` through `type_attribute` and `field_attribute`. Failures
+/// become `InvalidArgument` statuses carrying one google.rpc.BadRequest field
+/// violation per invalid field.
+///
+/// ```ignore
+/// let example::CreateExampleRequest { name, value } = request.into_valid()?;
+/// ```
+pub trait ValidateRequest<B> {
+    /// Checks the request message against its validation rules.
+    fn validate(&self) -> std::result::Result<(), tonic::Status>;
+
+    /// Checks the request message and gives it back when it is valid.
+    fn into_valid(self) -> std::result::Result<B, tonic::Status>;
+}
+
+impl<B: validator::Validate> ValidateRequest<B> for Request<B> {
+    fn validate(&self) -> std::result::Result<(), tonic::Status> {
+        match self.get_ref().validate() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::from(e).to_status()),
+        }
+    }
+
+    fn into_valid(self) -> std::result::Result<B, tonic::Status> {
+        ValidateRequest::validate(&self)?;
+        Ok(self.into_inner())
+    }
+}
+
 /// Returns error from a gRPC method.
 pub fn error<R: prost::Message>(
     error: ErrorCode,
@@ -83,3 +335,52 @@ pub fn ok<R: prost::Message>(res: R) -> std::result::Result<tonic::Response<R>,
     Ok(tonic::Response::new(res))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_error_to_status() {
+        let status: tonic::Status = Error::new(ErrorCode::AlreadyExists, Some("duplicated")).into();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        assert_eq!(status.message(), "duplicated");
+
+        let status: tonic::Status = crate::error::Error::NotFound.into();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        for code in [
+            ErrorCode::Unauthenticated,
+            ErrorCode::DataLoss,
+            ErrorCode::Validation,
+        ] {
+            assert_eq!(ErrorCode::from(tonic::Code::from(code)), code);
+        }
+    }
+
+    #[test]
+    pub fn test_validate_request() {
+        use validator::Validate;
+
+        #[derive(Validate)]
+        struct CreateExampleRequest {
+            #[validate(length(min = 1))]
+            name: String,
+        }
+
+        let request = Request::new(CreateExampleRequest {
+            name: "".to_string(),
+        });
+
+        let status = request.into_valid().err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let details = ErrorDetails::from_status(&status);
+        assert_eq!(details.field_violations()[0].field, "name");
+
+        let request = Request::new(CreateExampleRequest {
+            name: "example".to_string(),
+        });
+
+        assert!(ValidateRequest::validate(&request).is_ok());
+    }
+}
@@ -0,0 +1,574 @@
+// TLS of gRPC services, with client certificates verified against a client
+// CA when one is set (mutual TLS). Certificates and keys come from PEM files
+// or secret references, and can be reloaded while the service runs.
+
+use std::io::BufReader;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use logger::{fields::FieldValue, Logger};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+use crate::config::secrets::Secrets;
+use crate::config::{vars, Config, GetEnv};
+use crate::definition::GrpcDefinition;
+use crate::error::{Error, Result};
+
+/// How long clients have to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections waiting to be served after their handshakes.
+const PENDING_CONNECTIONS: usize = 128;
+
+/// TLS options of the `ServiceBuilder`, which take precedence over the
+/// settings file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsOptions {
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub client_ca: Option<String>,
+    pub reload: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TlsSettings {
+    cert: String,
+    key: String,
+    client_ca: Option<String>,
+    client_auth_optional: bool,
+    pub reload: Option<Duration>,
+}
+
+/// The identity of a client verified by mutual TLS, available to RPC methods
+/// in the extensions of its requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerIdentity {
+    /// The DNS names of the client certificate.
+    pub dns_names: Vec<String>,
+
+    /// The certificate chain presented by the client, DER encoded, starting
+    /// with its own.
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// Accepts TLS connections, with the last certificates loaded.
+pub(crate) struct TlsAcceptor {
+    settings: TlsSettings,
+    alpn: Vec<Vec<u8>>,
+    current: RwLock<(Vec<String>, Arc<ServerConfig>)>,
+}
+
+/// A TLS connection, served by tonic.
+pub(crate) struct TlsConnection<S>(tokio_rustls::server::TlsStream<S>);
+
+#[derive(Debug, Clone)]
+pub(crate) struct TlsConnectInfo<T> {
+    inner: T,
+    peer: Option<PeerIdentity>,
+}
+
+impl TlsSettings {
+    /// Loads the TLS settings, giving back None when TLS is disabled. The
+    /// GRPC_TLS_CERT, GRPC_TLS_KEY, GRPC_TLS_CLIENT_CA,
+    /// GRPC_TLS_CLIENT_AUTH_OPTIONAL and GRPC_TLS_RELOAD_INTERVAL environment
+    /// variables take precedence over the builder options, which take
+    /// precedence over the `[grpc.tls]` section of the settings file.
+    pub fn new(options: &TlsOptions, definition: &GrpcDefinition) -> Result<Option<Self>> {
+        let tls = definition.tls.as_ref();
+        let cert: Option<String> = Config::get_os_env(
+            "GRPC_TLS_CERT",
+            options.cert.clone().or_else(|| tls.map(|t| t.cert.clone())),
+        );
+        let key: Option<String> = Config::get_os_env(
+            "GRPC_TLS_KEY",
+            options.key.clone().or_else(|| tls.map(|t| t.key.clone())),
+        );
+        let client_ca: Option<String> = Config::get_os_env(
+            "GRPC_TLS_CLIENT_CA",
+            options
+                .client_ca
+                .clone()
+                .or_else(|| tls.and_then(|t| t.client_ca.clone())),
+        );
+        let client_auth_optional = vars::GRPC_TLS_CLIENT_AUTH_OPTIONAL
+            .get(tls.map(|t| t.client_auth_optional))
+            .unwrap_or(false);
+        // Intervals of the environment and of the settings file are in
+        // seconds, the one of the builder keeps its precision.
+        let reload = vars::GRPC_TLS_RELOAD_INTERVAL
+            .get(None)
+            .map(Duration::from_secs)
+            .or(options.reload)
+            .or_else(|| tls.and_then(|t| t.reload_interval).map(Duration::from_secs));
+
+        let (cert, key) = match (cert, key) {
+            (None, None) if client_ca.is_none() => return Ok(None),
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => {
+                return Err(invalid(
+                    "TLS client CA needs a certificate and a key".to_string(),
+                ))
+            }
+            _ => {
+                return Err(invalid(
+                    "TLS certificate and key must be set together".to_string(),
+                ))
+            }
+        };
+
+        Ok(Some(TlsSettings {
+            cert,
+            key,
+            client_ca,
+            client_auth_optional,
+            reload: reload.filter(|r| !r.is_zero()),
+        }))
+    }
+
+    /// Gives back the PEM contents of the certificate, the key and the client
+    /// CA, from files or secrets.
+    async fn sources(&self, secrets: &Secrets) -> Result<Vec<String>> {
+        let mut sources = Vec::new();
+
+        for source in [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            let content = match secrets.reference(source) {
+                Some(reference) => secrets.get(reference).await?.expose().to_string(),
+                None => tokio::fs::read_to_string(source)
+                    .await
+                    .map_err(|e| invalid(format!("TLS file '{}': {}", source, e)))?,
+            };
+
+            sources.push(content);
+        }
+
+        Ok(sources)
+    }
+}
+
+impl TlsAcceptor {
+    /// Loads the certificates, failing when they are invalid. Clients may
+    /// use HTTP/1.1 besides HTTP/2 when `http1` is set, for gRPC-Web.
+    pub async fn new(settings: &TlsSettings, http1: bool, secrets: &Secrets) -> Result<Self> {
+        let mut alpn = vec![b"h2".to_vec()];
+        if http1 {
+            alpn.push(b"http/1.1".to_vec());
+        }
+
+        let sources = settings.sources(secrets).await?;
+        let config = server_config(settings, &sources, &alpn)?;
+
+        Ok(TlsAcceptor {
+            settings: settings.clone(),
+            alpn,
+            current: RwLock::new((sources, Arc::new(config))),
+        })
+    }
+
+    /// Loads the certificates again, replacing the current ones for new
+    /// connections when they changed. Invalid certificates are rejected,
+    /// keeping the current ones.
+    pub async fn reload(&self, secrets: &Secrets) -> Result<bool> {
+        let sources = self.settings.sources(secrets).await?;
+        if self.current.read().unwrap().0 == sources {
+            return Ok(false);
+        }
+
+        let config = server_config(&self.settings, &sources, &self.alpn)?;
+        *self.current.write().unwrap() = (sources, Arc::new(config));
+        Ok(true)
+    }
+
+    pub fn reload_interval(&self) -> Option<Duration> {
+        self.settings.reload
+    }
+
+    fn config(&self) -> Arc<ServerConfig> {
+        self.current.read().unwrap().1.clone()
+    }
+}
+
+impl std::fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TlsAcceptor")
+            .field("settings", &self.settings)
+            .finish()
+    }
+}
+
+impl PeerIdentity {
+    fn new(certificates: Option<&[Certificate]>) -> Option<Self> {
+        let certificates: Vec<Vec<u8>> = certificates?.iter().map(|c| c.0.clone()).collect();
+        let end_entity = webpki::EndEntityCert::try_from(certificates.first()?.as_slice()).ok()?;
+
+        let dns_names = match end_entity.dns_names() {
+            Ok(names) => names.map(|name| <&str>::from(name).to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Some(PeerIdentity {
+            dns_names,
+            certificates,
+        })
+    }
+
+    /// Retrieves the client identity from RPC's request argument, none when
+    /// the client presented no certificate.
+    pub fn from_request<B>(request: &tonic::Request<B>) -> Option<PeerIdentity> {
+        request.extensions().get::<PeerIdentity>().cloned()
+    }
+}
+
+impl<S: Connected> Connected for TlsConnection<S> {
+    type ConnectInfo = TlsConnectInfo<S::ConnectInfo>;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        let (inner, session) = self.0.get_ref();
+
+        TlsConnectInfo {
+            inner: inner.connect_info(),
+            peer: PeerIdentity::new(session.peer_certificates()),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsConnection<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsConnection<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+/// Gives back the TLS connections of the accepted ones, after their
+/// handshakes, which run concurrently. Failed handshakes are logged and
+/// their connections dropped.
+pub(crate) fn incoming<I, S>(
+    acceptor: &Arc<TlsAcceptor>,
+    incoming: I,
+    logger: &Arc<Logger>,
+) -> impl Stream<Item = std::io::Result<TlsConnection<S>>>
+where
+    I: Stream<Item = std::io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (tx, rx) = tokio::sync::mpsc::channel(PENDING_CONNECTIONS);
+    let acceptor = acceptor.clone();
+    let logger = logger.clone();
+
+    tokio::spawn(async move {
+        futures::pin_mut!(incoming);
+
+        loop {
+            // Stops accepting connections once the server is gone.
+            let closed = tx.closed();
+            futures::pin_mut!(closed);
+
+            let connection = match futures::future::select(incoming.next(), closed).await {
+                futures::future::Either::Left((Some(connection), _)) => connection,
+                _ => break,
+            };
+
+            let stream = match connection {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    continue;
+                }
+            };
+
+            let tls = tokio_rustls::TlsAcceptor::from(acceptor.config());
+            let tx = tx.clone();
+            let logger = logger.clone();
+
+            tokio::spawn(async move {
+                let error = match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await
+                {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(TlsConnection(stream))).await;
+                        return;
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(_) => "handshake timed out".to_string(),
+                };
+
+                logger.debugf(
+                    "TLS handshake failed",
+                    logger::fields!("tls.error" => FieldValue::String(error)),
+                );
+            });
+        }
+    });
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|connection| (connection, rx))
+    })
+}
+
+/// Puts the client address and identity of TLS connections in the request
+/// extensions, where tonic and RPC methods look for them.
+pub(crate) fn expose_peer(extensions: &mut http::Extensions) {
+    let peer = if let Some(info) = extensions.get::<TlsConnectInfo<TcpConnectInfo>>().cloned() {
+        extensions.insert(info.inner);
+        info.peer
+    } else {
+        extensions
+            .get::<TlsConnectInfo<()>>()
+            .and_then(|info| info.peer.clone())
+    };
+
+    if let Some(peer) = peer {
+        extensions.insert(peer);
+    }
+}
+
+fn server_config(
+    settings: &TlsSettings,
+    sources: &[String],
+    alpn: &[Vec<u8>],
+) -> Result<ServerConfig> {
+    let certs = certificates(&settings.cert, &sources[0])?;
+    let key = private_key(&settings.key, &sources[1])?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let builder = match &settings.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(name) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(name, &sources[2])? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| invalid(format!("TLS client CA '{}': {}", name, e)))?;
+            }
+
+            if settings.client_auth_optional {
+                builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                )
+            } else {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+        }
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(format!("TLS certificate '{}': {}", settings.cert, e)))?;
+
+    config.alpn_protocols = alpn.to_vec();
+    Ok(config)
+}
+
+fn certificates(name: &str, content: &str) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(content.as_bytes()))
+        .map_err(|e| invalid(format!("TLS certificate '{}': {}", name, e)))?;
+
+    if certs.is_empty() {
+        return Err(invalid(format!(
+            "TLS certificate '{}' has no certificates",
+            name
+        )));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn private_key(name: &str, content: &str) -> Result<PrivateKey> {
+    let items = rustls_pemfile::read_all(&mut BufReader::new(content.as_bytes()))
+        .map_err(|e| invalid(format!("TLS key '{}': {}", name, e)))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(format!("TLS key '{}' has no private key", name)))
+}
+
+fn invalid(message: String) -> Error {
+    Error::ServerSettings(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::listener::{tcp_incoming, BindAddress, Listener};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{ClientConfig, ServerName};
+
+    const CA: &str = include_str!("testdata/ca.pem");
+    const SERVER_CERT: &str = include_str!("testdata/server.pem");
+    const SERVER_KEY: &str = include_str!("testdata/server.key");
+    const SERVER2_CERT: &str = include_str!("testdata/server2.pem");
+    const SERVER2_KEY: &str = include_str!("testdata/server2.key");
+    const CLIENT_CERT: &str = include_str!("testdata/client.pem");
+    const CLIENT_KEY: &str = include_str!("testdata/client.key");
+
+    fn client(with_cert: bool) -> tokio_rustls::TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(&certificates("ca", CA).unwrap()[0]).unwrap();
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+
+        let mut config = if with_cert {
+            builder
+                .with_client_auth_cert(
+                    certificates("client", CLIENT_CERT).unwrap(),
+                    private_key("client", CLIENT_KEY).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        tokio_rustls::TlsConnector::from(Arc::new(config))
+    }
+
+    #[test]
+    pub fn test_tls_settings() {
+        let definition: GrpcDefinition = toml::from_str(
+            r#"
+            [tls]
+            cert = "server.pem"
+            key = "server.key"
+            client_ca = "ca.pem"
+            reload_interval = 60
+        "#,
+        )
+        .unwrap();
+
+        let settings = TlsSettings::new(&TlsOptions::default(), &definition)
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.client_ca.as_deref(), Some("ca.pem"));
+        assert_eq!(settings.reload, Some(Duration::from_secs(60)));
+
+        let options = TlsOptions {
+            reload: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let settings = TlsSettings::new(&options, &definition).unwrap().unwrap();
+        assert_eq!(settings.reload, Some(Duration::from_millis(500)));
+
+        let options = TlsOptions {
+            cert: Some("other.pem".to_string()),
+            ..Default::default()
+        };
+        let settings = TlsSettings::new(&options, &definition).unwrap().unwrap();
+        assert_eq!(settings.cert, "other.pem");
+        assert_eq!(settings.key, "server.key");
+
+        let definition = GrpcDefinition::default();
+        assert_eq!(
+            TlsSettings::new(&TlsOptions::default(), &definition).unwrap(),
+            None
+        );
+        assert!(TlsSettings::new(&options, &definition).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_tls_acceptor() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("server.pem"), SERVER_CERT).unwrap();
+        std::fs::write(dir.join("server.key"), SERVER_KEY).unwrap();
+
+        let mut env = crate::testing::EnvGuard::new();
+        env.set("POCKET_TEST_TLS_CA", CA);
+        let options = TlsOptions {
+            cert: Some(dir.join("server.pem").display().to_string()),
+            key: Some(dir.join("server.key").display().to_string()),
+            client_ca: Some("secret:env:POCKET_TEST_TLS_CA".to_string()),
+            reload: None,
+        };
+        let settings = TlsSettings::new(&options, &GrpcDefinition::default())
+            .unwrap()
+            .unwrap();
+
+        let secrets = Secrets::new(&[]);
+        let acceptor = Arc::new(TlsAcceptor::new(&settings, false, &secrets).await.unwrap());
+        assert!(!acceptor.reload(&secrets).await.unwrap());
+
+        let listener = match BindAddress::new("127.0.0.1", 0, false)
+            .unwrap()
+            .bind()
+            .await
+            .unwrap()
+        {
+            Listener::Tcp(listener) => listener,
+            #[cfg(unix)]
+            _ => unreachable!(),
+        };
+        let address = listener.local_addr().unwrap();
+        let logger = Arc::new(logger::builder::LoggerBuilder::new().build());
+        let mut connections = Box::pin(incoming(&acceptor, tcp_incoming(listener), &logger));
+        let name = ServerName::try_from("localhost").unwrap();
+
+        // Clients without certificates are rejected.
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut rejected = client(false).connect(name.clone(), stream).await.unwrap();
+        let _ = rejected.write_all(b"ping").await;
+        let mut buf = [0u8; 4];
+        assert!(!matches!(rejected.read(&mut buf).await, Ok(n) if n > 0));
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut accepted = client(true).connect(name.clone(), stream).await.unwrap();
+        accepted.write_all(b"ping").await.unwrap();
+
+        let mut connection = connections.next().await.unwrap().unwrap();
+        let info = connection.connect_info();
+        assert_eq!(
+            info.inner.remote_addr(),
+            accepted.get_ref().0.local_addr().ok()
+        );
+        assert_eq!(info.peer.unwrap().dns_names, vec!["client.example"]);
+
+        connection.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        std::fs::write(dir.join("server.pem"), SERVER2_CERT).unwrap();
+        std::fs::write(dir.join("server.key"), SERVER2_KEY).unwrap();
+        assert!(acceptor.reload(&secrets).await.unwrap());
+        assert!(!acceptor.reload(&secrets).await.unwrap());
+
+        std::fs::write(dir.join("server.key"), "invalid").unwrap();
+        assert!(acceptor.reload(&secrets).await.is_err());
+        assert_eq!(acceptor.current.read().unwrap().0[0], SERVER2_CERT);
+    }
+}
@@ -0,0 +1,585 @@
+// gRPC-Web support, so browsers can call gRPC services directly. Requests are
+// translated into regular gRPC ones, and responses carry their trailers
+// inside the body, as the gRPC-Web protocol requires.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::Body as HttpBody;
+use rocket::data::ByteUnit;
+use tonic::body::{empty_body, BoxBody};
+use tonic::transport::Body;
+use tower::{Layer, Service};
+
+use crate::config::{vars, Config, GetEnv};
+use crate::definition::GrpcDefinition;
+use crate::error::{Error, Result};
+use crate::http::cors::CorsSettings;
+
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// Request headers always allowed from gRPC-Web clients.
+const ALLOWED_HEADERS: [&str; 6] = [
+    "content-type",
+    "x-grpc-web",
+    "x-user-agent",
+    "grpc-timeout",
+    "authorization",
+    "x-request-id",
+];
+
+/// Response headers always exposed to gRPC-Web clients.
+const EXPOSED_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+/// Flag of the frame carrying the trailers at the end of the response body.
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Default limit of a decoded gRPC-Web text body, the same as the message
+/// size limit of gRPC servers.
+const DEFAULT_MAX_BODY_SIZE: ByteUnit = ByteUnit::Mebibyte(4);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GrpcWebSettings {
+    cors: Option<CorsSettings>,
+    max_body_size: usize,
+}
+
+impl GrpcWebSettings {
+    /// Loads the gRPC-Web settings, giving back None when it is disabled.
+    /// The GRPC_WEB environment variable takes precedence over the builder
+    /// option, which takes precedence over the settings file.
+    pub fn new(enabled: Option<bool>, definition: &GrpcDefinition) -> Result<Option<Self>> {
+        let enabled = vars::GRPC_WEB
+            .get(Some(enabled.unwrap_or(definition.web)))
+            .unwrap_or(false);
+
+        if !enabled {
+            return Ok(None);
+        }
+
+        let max_body_size = match Config::get_os_env(
+            "GRPC_WEB_MAX_BODY_SIZE",
+            definition.web_max_body_size.clone(),
+        ) {
+            Some(size) => size.parse::<ByteUnit>().map_err(|_| {
+                Error::ServerSettings(format!("web_max_body_size '{}' is not a size", size))
+            })?,
+            None => DEFAULT_MAX_BODY_SIZE,
+        };
+
+        Ok(Some(GrpcWebSettings {
+            cors: CorsSettings::new(&definition.cors, &vars::GRPC_CORS)?,
+            max_body_size: max_body_size.as_u64().try_into().unwrap_or(usize::MAX),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Binary,
+    Text,
+}
+
+impl Encoding {
+    fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+
+        if content_type.starts_with(GRPC_WEB_TEXT) {
+            Some(Encoding::Text)
+        } else if content_type.starts_with(GRPC_WEB) {
+            Some(Encoding::Binary)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Binary => "application/grpc-web+proto",
+            Encoding::Text => "application/grpc-web-text+proto",
+        }
+    }
+}
+
+/// Translates gRPC-Web requests when the settings enable it, passing
+/// everything else untouched to the inner service.
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcWebLayer {
+    settings: Option<Arc<GrpcWebSettings>>,
+}
+
+impl GrpcWebLayer {
+    pub(crate) fn new(settings: &Option<GrpcWebSettings>) -> Self {
+        GrpcWebLayer {
+            settings: settings.clone().map(Arc::new),
+        }
+    }
+}
+
+impl<S> Layer<S> for GrpcWebLayer {
+    type Service = GrpcWebService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        GrpcWebService {
+            inner: service,
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcWebService<S> {
+    inner: S,
+    settings: Option<Arc<GrpcWebSettings>>,
+}
+
+impl<S> Service<Request<Body>> for GrpcWebService<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        futures::future::BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // See the GrpcMiddleware on why the inner service is replaced.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let settings = match &self.settings {
+            Some(settings) => settings.clone(),
+            None => return Box::pin(inner.call(request)),
+        };
+
+        let origin = request
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|o| o.to_str().ok())
+            .map(|o| o.to_string());
+
+        if request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            let response = preflight(&settings, origin.as_deref(), request.headers());
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let encoding = match Encoding::from_content_type(request.headers()) {
+            Some(encoding) => encoding,
+            None => return Box::pin(inner.call(request)),
+        };
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let body = match encoding {
+                Encoding::Binary => body,
+                Encoding::Text => match decode_text_body(body, settings.max_body_size).await {
+                    Ok(body) => Body::from(body),
+                    Err(status) => {
+                        let mut response = status.to_http();
+                        add_cors_headers(&settings, origin.as_deref(), response.headers_mut());
+                        return Ok(response);
+                    }
+                },
+            };
+
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/grpc"),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+            let (mut parts, body) = response.into_parts();
+
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(encoding.content_type()),
+            );
+            add_cors_headers(&settings, origin.as_deref(), &mut parts.headers);
+
+            let body = GrpcWebBody {
+                inner: body,
+                encoding,
+                done: false,
+            };
+
+            Ok(Response::from_parts(parts, body.boxed_unsync()))
+        })
+    }
+}
+
+fn add_cors_headers(settings: &GrpcWebSettings, origin: Option<&str>, headers: &mut HeaderMap) {
+    let (cors, origin) = match (&settings.cors, origin) {
+        (Some(cors), Some(origin)) if cors.allows_origin(origin) => (cors, origin),
+        _ => return,
+    };
+
+    let allow_origin = if cors.credentials || !cors.origins.iter().any(|o| o == "*") {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        origin
+    } else {
+        "*"
+    };
+
+    if let Ok(value) = HeaderValue::from_str(allow_origin) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+    }
+
+    if cors.credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    let mut exposed: Vec<&str> = EXPOSED_HEADERS.to_vec();
+    exposed.extend(cors.expose_headers.iter().map(|h| h.as_str()));
+    if let Ok(value) = HeaderValue::from_str(&exposed.join(", ")) {
+        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+    }
+}
+
+fn preflight(
+    settings: &GrpcWebSettings,
+    origin: Option<&str>,
+    headers: &HeaderMap,
+) -> Response<BoxBody> {
+    let forbidden = || {
+        let mut response = Response::new(empty_body());
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        response
+    };
+
+    let (cors, origin) = match (&settings.cors, origin) {
+        (Some(cors), Some(origin)) if cors.allows_origin(origin) => (cors, origin),
+        _ => return forbidden(),
+    };
+
+    let method = headers
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|m| m.to_str().ok())
+        .unwrap_or_default();
+
+    let requested_headers = headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    // Headers used by the gRPC-Web protocol are always allowed.
+    let custom_headers: Vec<&str> = requested_headers
+        .split(',')
+        .map(|h| h.trim())
+        .filter(|h| !ALLOWED_HEADERS.iter().any(|a| a.eq_ignore_ascii_case(h)))
+        .collect();
+
+    let allowed = cors.methods.iter().any(|m| m.as_str() == method)
+        && cors.allows_headers(&custom_headers.join(","));
+
+    if !allowed {
+        return forbidden();
+    }
+
+    let mut response = Response::new(empty_body());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+
+    let response_headers = response.headers_mut();
+    add_cors_headers(settings, Some(origin), response_headers);
+
+    let methods: Vec<&str> = cors.methods.iter().map(|m| m.as_str()).collect();
+    let mut allowed_headers: Vec<&str> = ALLOWED_HEADERS.to_vec();
+    if cors.headers.iter().any(|h| h == "*") {
+        allowed_headers.extend(custom_headers);
+    } else {
+        allowed_headers.extend(cors.headers.iter().map(|h| h.as_str()));
+    }
+
+    for (name, value) in [
+        (header::ACCESS_CONTROL_ALLOW_METHODS, methods.join(", ")),
+        (
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            allowed_headers.join(", "),
+        ),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response_headers.insert(name, value);
+        }
+    }
+
+    if let Some(max_age) = cors.max_age {
+        response_headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+
+    response
+}
+
+/// Reads a base64 encoded request body, up to `limit` decoded bytes.
+/// Clients may send it as a sequence of padded chunks, so it is decoded in
+/// groups of 4 characters as they arrive.
+async fn decode_text_body(
+    mut body: Body,
+    limit: usize,
+) -> std::result::Result<Vec<u8>, tonic::Status> {
+    let mut decoded = Vec::new();
+    let mut pending = Vec::with_capacity(4);
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| tonic::Status::internal(e.to_string()))?;
+        for c in chunk.iter().filter(|c| !c.is_ascii_whitespace()) {
+            pending.push(*c);
+            if pending.len() == 4 {
+                decode_group(&pending, &mut decoded, limit)?;
+                pending.clear();
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        decode_group(&pending, &mut decoded, limit)?;
+    }
+
+    Ok(decoded)
+}
+
+fn decode_group(
+    group: &[u8],
+    decoded: &mut Vec<u8>,
+    limit: usize,
+) -> std::result::Result<(), tonic::Status> {
+    let bytes = base64::decode(group)
+        .map_err(|_| tonic::Status::invalid_argument("invalid gRPC-Web text body"))?;
+
+    if decoded.len() + bytes.len() > limit {
+        return Err(tonic::Status::resource_exhausted(format!(
+            "gRPC-Web text body is larger than {} bytes",
+            limit
+        )));
+    }
+
+    decoded.extend(bytes);
+    Ok(())
+}
+
+/// Encodes the response trailers as the last frame of a gRPC-Web body.
+fn trailers_frame(trailers: &HeaderMap) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.push(b':');
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = Vec::with_capacity(block.len() + 5);
+    frame.push(TRAILERS_FLAG);
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    frame.extend(block);
+    frame
+}
+
+/// A response body that, after the inner body data, sends its trailers as
+/// a body frame.
+struct GrpcWebBody {
+    inner: BoxBody,
+    encoding: Encoding,
+    done: bool,
+}
+
+impl GrpcWebBody {
+    fn encode(&self, data: Bytes) -> Bytes {
+        match self.encoding {
+            Encoding::Binary => data,
+            Encoding::Text => Bytes::from(base64::encode(&data)),
+        }
+    }
+}
+
+impl HttpBody for GrpcWebBody {
+    type Data = Bytes;
+    type Error = tonic::Status;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut this.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(this.encode(data)))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => match Pin::new(&mut this.inner).poll_trailers(cx) {
+                Poll::Ready(Ok(trailers)) => {
+                    this.done = true;
+                    match trailers {
+                        Some(trailers) => {
+                            let frame = Bytes::from(trailers_frame(&trailers));
+                            Poll::Ready(Some(Ok(this.encode(frame))))
+                        }
+                        None => Poll::Ready(None),
+                    }
+                }
+                Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+                Poll::Pending => Poll::Pending,
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::definition::CorsDefinition;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<Body>> for Echo {
+        type Response = Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = futures::future::Ready<std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let mut response = Response::new(empty_body());
+            response.headers_mut().insert(
+                "x-content-type",
+                request.headers()[header::CONTENT_TYPE].clone(),
+            );
+            futures::future::ready(Ok(response))
+        }
+    }
+
+    fn service() -> GrpcWebService<Echo> {
+        let definition = GrpcDefinition {
+            web: true,
+            cors: CorsDefinition {
+                origins: vec!["https://example.com".to_string()],
+                ..CorsDefinition::default()
+            },
+            ..GrpcDefinition::default()
+        };
+
+        let settings = GrpcWebSettings::new(None, &definition).unwrap();
+        GrpcWebLayer::new(&settings).layer(Echo)
+    }
+
+    async fn decode(
+        chunks: &[&'static str],
+        limit: usize,
+    ) -> std::result::Result<Vec<u8>, tonic::Status> {
+        let (mut sender, body) = Body::channel();
+        let chunks = chunks.to_vec();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                if sender
+                    .send_data(Bytes::from_static(chunk.as_bytes()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        decode_text_body(body, limit).await
+    }
+
+    #[tokio::test]
+    pub async fn test_grpc_web_frames() {
+        assert_eq!(decode(&["AAAAAAA="], 5).await.unwrap(), vec![0; 5]);
+        assert_eq!(decode(&["AQ==Ag=="], 5).await.unwrap(), vec![1, 2]);
+        assert_eq!(decode(&["AA", "AAA", "AA="], 5).await.unwrap(), vec![0; 5]);
+        assert!(decode(&["A!=="], 5).await.is_err());
+
+        let status = decode(&["AAAA", "AAAA"], 5).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        assert_eq!(
+            trailers_frame(&trailers),
+            b"\x80\x00\x00\x00\x0fgrpc-status:0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_grpc_web_service() {
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/grpc-web-text")
+            .header(header::ORIGIN, "https://example.com")
+            .body(Body::from("AAAAAAA="))
+            .unwrap();
+
+        let response = service().call(request).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type"], "application/grpc");
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "application/grpc-web-text+proto"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "x-grpc-web, content-type",
+            )
+            .body(Body::empty())
+            .unwrap();
+
+        let response = service().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://other.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = service().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,359 @@
+// Cross-Origin Resource Sharing for HTTP services, configured by the
+// `[http.cors]` section of the settings file.
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::{Request, Response};
+
+use crate::config::vars::CorsVars;
+use crate::config::{Config, GetEnv};
+use crate::definition::CorsDefinition;
+use crate::error::{Error, Result};
+use crate::http::fairing::REQUEST_ID_HEADER;
+
+const DEFAULT_METHODS: [&str; 6] = ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+const DEFAULT_HEADERS: [&str; 3] = ["Authorization", "Content-Type", REQUEST_ID_HEADER];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CorsSettings {
+    pub origins: Vec<String>,
+    pub methods: Vec<Method>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl CorsSettings {
+    /// Loads and validates the CORS settings, giving back None when no
+    /// origin is allowed. Environment variables, named after the prefix of
+    /// `vars` (like `HTTP_CORS_ORIGINS`), take precedence over the file
+    /// values.
+    pub(crate) fn new(definition: &CorsDefinition, vars: &CorsVars) -> Result<Option<Self>> {
+        let env = |name: &str| format!("{}_{}", vars.prefix, name);
+        let origins = env_list(&env("ORIGINS")).unwrap_or_else(|| definition.origins.clone());
+        if origins.is_empty() {
+            return Ok(None);
+        }
+
+        for origin in &origins {
+            if !is_valid_origin(origin) {
+                return Err(invalid(format!("CORS origin '{}' is invalid", origin)));
+            }
+        }
+
+        let methods = env_list(&env("METHODS"))
+            .or_else(|| definition.methods.clone())
+            .unwrap_or_else(|| DEFAULT_METHODS.iter().map(|m| m.to_string()).collect())
+            .iter()
+            .map(|m| {
+                Method::from_str(m).map_err(|_| invalid(format!("CORS method '{}' is invalid", m)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let headers = env_list(&env("HEADERS"))
+            .or_else(|| definition.headers.clone())
+            .unwrap_or_else(|| DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect());
+
+        let expose_headers = definition
+            .expose_headers
+            .clone()
+            .unwrap_or_else(|| vec![REQUEST_ID_HEADER.to_string()]);
+
+        let credentials = vars
+            .credentials
+            .get(Some(definition.credentials.unwrap_or(false)))
+            .unwrap_or(false);
+
+        if credentials && origins.iter().any(|o| o == "*") {
+            return Err(invalid(
+                "CORS credentials can not be used with any origin ('*')".to_string(),
+            ));
+        }
+
+        Ok(Some(CorsSettings {
+            origins,
+            methods,
+            headers,
+            expose_headers,
+            credentials,
+            max_age: vars.max_age.get(definition.max_age),
+        }))
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o == "*" || o == origin)
+    }
+
+    pub fn allows_headers(&self, headers: &str) -> bool {
+        if self.headers.iter().any(|h| h == "*") {
+            return true;
+        }
+
+        headers
+            .split(',')
+            .map(|h| h.trim())
+            .filter(|h| !h.is_empty())
+            .all(|h| self.headers.iter().any(|a| a.eq_ignore_ascii_case(h)))
+    }
+}
+
+/// Adds the CORS headers to the responses of allowed origins, and answers
+/// preflight requests.
+#[derive(Debug)]
+pub(crate) struct Cors {
+    settings: CorsSettings,
+}
+
+impl Cors {
+    pub fn new(settings: &CorsSettings) -> Self {
+        Cors {
+            settings: settings.clone(),
+        }
+    }
+
+    fn preflight<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>, method: &str) {
+        let headers = request
+            .headers()
+            .get_one("Access-Control-Request-Headers")
+            .unwrap_or_default();
+
+        let allowed = Method::from_str(method)
+            .map(|m| self.settings.methods.contains(&m))
+            .unwrap_or(false)
+            && self.settings.allows_headers(headers);
+
+        // Preflight requests are answered here, regardless of the routes
+        // matching them.
+        response.set_sized_body(0, Cursor::new(""));
+        response.remove_header("Content-Type");
+
+        if !allowed {
+            response.set_status(Status::Forbidden);
+            return;
+        }
+
+        response.set_status(Status::NoContent);
+
+        let methods: Vec<&str> = self.settings.methods.iter().map(|m| m.as_str()).collect();
+        response.set_raw_header("Access-Control-Allow-Methods", methods.join(", "));
+
+        if self.settings.headers.iter().any(|h| h == "*") {
+            if !headers.is_empty() {
+                response.set_raw_header("Access-Control-Allow-Headers", headers.to_string());
+            }
+        } else {
+            response.set_raw_header(
+                "Access-Control-Allow-Headers",
+                self.settings.headers.join(", "),
+            );
+        }
+
+        if let Some(max_age) = self.settings.max_age {
+            response.set_raw_header("Access-Control-Max-Age", max_age.to_string());
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        let preflight = match request.headers().get_one("Access-Control-Request-Method") {
+            Some(method) if request.method() == Method::Options => Some(method),
+            _ => None,
+        };
+
+        if !self.settings.allows_origin(origin) {
+            if preflight.is_some() {
+                response.set_sized_body(0, Cursor::new(""));
+                response.set_status(Status::Forbidden);
+            }
+
+            return;
+        }
+
+        if self.settings.credentials || !self.settings.origins.iter().any(|o| o == "*") {
+            response.set_raw_header("Access-Control-Allow-Origin", origin.to_string());
+            response.adjoin_raw_header("Vary", "Origin");
+        } else {
+            response.set_raw_header("Access-Control-Allow-Origin", "*");
+        }
+
+        if self.settings.credentials {
+            response.set_raw_header("Access-Control-Allow-Credentials", "true");
+        }
+
+        match preflight {
+            Some(method) => self.preflight(request, response, method),
+            None if !self.settings.expose_headers.is_empty() => {
+                response.set_raw_header(
+                    "Access-Control-Expose-Headers",
+                    self.settings.expose_headers.join(", "),
+                );
+            }
+            None => {}
+        }
+    }
+}
+
+fn env_list(key: &str) -> Option<Vec<String>> {
+    let value: Option<String> = Config::get_os_env(key, None);
+    value.map(|value| {
+        value
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    })
+}
+
+fn invalid(message: String) -> Error {
+    Error::HttpSettings(message)
+}
+
+/// An origin is either `*` or a scheme and host, with an optional port,
+/// like `https://example.com:8443`.
+fn is_valid_origin(origin: &str) -> bool {
+    if origin == "*" {
+        return true;
+    }
+
+    let host = match origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+    {
+        Some(host) => host,
+        None => return false,
+    };
+
+    !host.is_empty() && !host.contains(['/', '?', '#', ' '])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::vars::EnvVar;
+
+    const TEST_CORS: CorsVars = CorsVars {
+        prefix: "TEST_CORS",
+        credentials: EnvVar::new("TEST_CORS_CREDENTIALS", "a boolean"),
+        max_age: EnvVar::new("TEST_CORS_MAX_AGE", "a number of seconds"),
+    };
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    #[rocket::get("/examples")]
+    fn examples() -> &'static str {
+        "[]"
+    }
+
+    #[test]
+    pub fn test_cors_settings() {
+        assert_eq!(
+            CorsSettings::new(&CorsDefinition::default(), &TEST_CORS).unwrap(),
+            None
+        );
+
+        let mut definition = CorsDefinition {
+            origins: vec!["example.com".to_string()],
+            ..CorsDefinition::default()
+        };
+        assert!(CorsSettings::new(&definition, &TEST_CORS).is_err());
+
+        definition.origins = vec!["*".to_string()];
+        definition.credentials = Some(true);
+        assert!(CorsSettings::new(&definition, &TEST_CORS).is_err());
+
+        definition.credentials = None;
+        definition.methods = Some(vec!["FETCH".to_string()]);
+        assert!(CorsSettings::new(&definition, &TEST_CORS).is_err());
+    }
+
+    #[test]
+    pub fn test_cors_fairing() {
+        let settings = CorsSettings::new(
+            &CorsDefinition {
+                origins: vec!["https://example.com".to_string()],
+                credentials: Some(true),
+                max_age: Some(600),
+                ..CorsDefinition::default()
+            },
+            &TEST_CORS,
+        )
+        .unwrap()
+        .unwrap();
+
+        let rocket = rocket::custom(rocket::Config::debug_default())
+            .mount("/", rocket::routes![examples])
+            .attach(Cors::new(&settings));
+
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client
+            .options("/examples")
+            .header(Header::new("Origin", "https://example.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .header(Header::new(
+                "Access-Control-Request-Headers",
+                "content-type",
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(headers.get_one("Access-Control-Max-Age"), Some("600"));
+
+        let response = client
+            .options("/examples")
+            .header(Header::new("Origin", "https://example.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .header(Header::new("Access-Control-Request-Headers", "x-custom"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .options("/examples")
+            .header(Header::new("Origin", "https://other.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            None
+        );
+
+        let response = client
+            .get("/examples")
+            .header(Header::new("Origin", "https://example.com"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+    }
+}
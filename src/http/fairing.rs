@@ -0,0 +1,394 @@
+// Rocket fairings and catchers attached to every HTTP service, since rocket
+// runs with its own log disabled.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Once, OnceLock};
+use std::time::Instant;
+
+use logger::fields::FieldValue;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket::{Data, Request, Response};
+
+use crate::http::error_body;
+use crate::service::Service;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest `X-Request-Id` accepted from clients.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The identifier of a HTTP request, taken from its `X-Request-Id` header
+/// or generated when the header is absent or invalid. It can also be used
+/// as a request guard.
+#[derive(Debug, Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> Self {
+        RequestId(format!("{:032x}", rand::random::<u128>()))
+    }
+
+    fn from_request(request: &Request<'_>) -> Self {
+        match request.headers().get_one(REQUEST_ID_HEADER) {
+            Some(id) if RequestId::is_valid(id) => RequestId(id.to_string()),
+            _ => RequestId::generate(),
+        }
+    }
+
+    /// Tells if an identifier given by a client can be echoed back and
+    /// logged: up to 128 letters, digits, '.', '_' or '-'.
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r RequestId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(request.local_cache(|| RequestId::from_request(request)))
+    }
+}
+
+/// Assigns an identifier to every request, echoing it back through the
+/// `X-Request-Id` response header.
+#[derive(Debug, Default)]
+pub struct RequestIdFairing;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ID",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = RequestId::from_request(request);
+        request.local_cache(|| id);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let id = request.local_cache(|| RequestId::from_request(request));
+        response.set_header(Header::new(REQUEST_ID_HEADER, id.0.clone()));
+    }
+}
+
+struct RequestStart(Instant);
+
+/// Logs every request through the service logger.
+#[derive(Debug, Default)]
+pub struct AccessLog;
+
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let service = match request.rocket().state::<Arc<Service>>() {
+            Some(service) if service.access_log() => service,
+            _ => return,
+        };
+
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let id = request.local_cache(|| RequestId::from_request(request));
+
+        service.logger.infof(
+            "request handled",
+            logger::fields! {
+                "http.method" => FieldValue::String(request.method().to_string()),
+                "http.path" => FieldValue::String(request.uri().path().to_string()),
+                "http.status" => FieldValue::String(response.status().code.to_string()),
+                "http.duration_ms" => FieldValue::String(start.0.elapsed().as_millis().to_string()),
+                "http.request_id" => FieldValue::String(id.to_string()),
+            },
+        );
+    }
+}
+
+/// A panic of a request handler, kept for the catcher of the request.
+struct Panic {
+    message: String,
+    location: String,
+}
+
+/// The panics of the requests being handled, by the task handling them.
+/// Only the tasks registered by a `PanicSlot` have an entry.
+fn panics() -> MutexGuard<'static, HashMap<tokio::task::Id, Option<Panic>>> {
+    static PANICS: OnceLock<Mutex<HashMap<tokio::task::Id, Option<Panic>>>> = OnceLock::new();
+
+    PANICS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers the task handling a request, so that the panic hook keeps the
+/// panic of its handler until the catcher takes it. Rocket runs the handler
+/// and the catcher as part of the request future, whichever the thread
+/// polling it. The registration ends when the request is dropped.
+struct PanicSlot(Option<tokio::task::Id>);
+
+impl PanicSlot {
+    fn register() -> Self {
+        let task = tokio::task::try_id();
+        if let Some(task) = task {
+            panics().insert(task, None);
+        }
+
+        PanicSlot(task)
+    }
+
+    fn take(&self) -> Option<Panic> {
+        let task = self.0?;
+        panics().get_mut(&task).and_then(Option::take)
+    }
+}
+
+impl Drop for PanicSlot {
+    fn drop(&mut self) {
+        if let Some(task) = self.0 {
+            panics().remove(&task);
+        }
+    }
+}
+
+/// Registers every request for the panics of its handler to reach the
+/// internal error catcher.
+#[derive(Debug, Default)]
+pub(crate) struct PanicFairing;
+
+#[rocket::async_trait]
+impl Fairing for PanicFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Panic capture",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(PanicSlot::register);
+    }
+}
+
+/// Answers internal errors, including handler panics, with a JSON error
+/// body, logging the request that caused it.
+#[rocket::catch(500)]
+fn internal_error(request: &Request<'_>) -> (Status, (ContentType, String)) {
+    let panic = request.local_cache(|| PanicSlot(None)).take();
+
+    if let Some(service) = request.rocket().state::<Arc<Service>>() {
+        let id = request.local_cache(|| RequestId::from_request(request));
+        let method = request.method().to_string();
+        let path = request.uri().path().to_string();
+
+        match panic {
+            Some(panic) => service.logger.errorf(
+                "handler panicked",
+                logger::fields! {
+                    "http.method" => FieldValue::String(method),
+                    "http.path" => FieldValue::String(path),
+                    "http.request_id" => FieldValue::String(id.to_string()),
+                    "panic.message" => FieldValue::String(panic.message),
+                    "panic.location" => FieldValue::String(panic.location),
+                },
+            ),
+            None => service.logger.errorf(
+                "internal server error",
+                logger::fields! {
+                    "http.method" => FieldValue::String(method),
+                    "http.path" => FieldValue::String(path),
+                    "http.request_id" => FieldValue::String(id.to_string()),
+                },
+            ),
+        }
+    }
+
+    let status = tonic::Status::internal("internal server error");
+    (
+        Status::InternalServerError,
+        (ContentType::JSON, error_body(&status)),
+    )
+}
+
+/// Gives back the catchers of a HTTP service, the ones it does not declare
+/// itself.
+pub(crate) fn catchers(rocket: &rocket::Rocket<rocket::Build>) -> Vec<rocket::Catcher> {
+    let root = rocket::http::uri::Origin::parse("/").ok();
+
+    rocket::catchers![internal_error]
+        .into_iter()
+        .filter(|catcher| {
+            !rocket
+                .catchers()
+                .any(|c| c.code == catcher.code && Some(&c.base) == root.as_ref())
+        })
+        .collect()
+}
+
+/// Keeps the details of the panics of registered requests for the catcher,
+/// so that handler panics are logged through the service logger. Rocket recovers from them by itself,
+/// but only reports them through its own (disabled) log. The previous hook
+/// still runs for every panic.
+pub(crate) fn set_panic_hook() {
+    static HOOK: Once = Once::new();
+
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => match info.payload().downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None => "unknown panic".to_string(),
+                },
+            };
+
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+
+            if let Some(task) = tokio::task::try_id() {
+                if let Some(slot) = panics().get_mut(&task) {
+                    *slot = Some(Panic { message, location });
+                }
+            }
+
+            previous(info);
+        }));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[rocket::get("/")]
+    fn index(id: &RequestId) -> String {
+        id.to_string()
+    }
+
+    #[test]
+    pub fn test_request_id() {
+        let rocket = rocket::custom(rocket::Config::debug_default())
+            .mount("/", rocket::routes![index])
+            .attach(RequestIdFairing);
+
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client
+            .get("/")
+            .header(Header::new(REQUEST_ID_HEADER, "abc"))
+            .dispatch();
+        assert_eq!(response.headers().get_one(REQUEST_ID_HEADER), Some("abc"));
+        assert_eq!(response.into_string().as_deref(), Some("abc"));
+
+        let response = client.get("/").dispatch();
+        let id = response
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .map(|s| s.to_string())
+            .unwrap();
+        assert_eq!(id.len(), 32);
+        assert_eq!(response.into_string(), Some(id));
+
+        for invalid in ["a\tb", "a b", &"a".repeat(129)] {
+            let response = client
+                .get("/")
+                .header(Header::new(REQUEST_ID_HEADER, invalid.to_string()))
+                .dispatch();
+            assert_eq!(
+                response
+                    .headers()
+                    .get_one(REQUEST_ID_HEADER)
+                    .map(|id| id.len()),
+                Some(32)
+            );
+        }
+    }
+
+    #[rocket::get("/panic")]
+    fn panic() -> String {
+        panic!("handler bug")
+    }
+
+    #[rocket::catch(500)]
+    fn custom_error() -> &'static str {
+        "custom"
+    }
+
+    #[test]
+    pub fn test_catchers() {
+        set_panic_hook();
+
+        let rocket =
+            rocket::custom(rocket::Config::debug_default()).mount("/", rocket::routes![panic]);
+        let defaults = catchers(&rocket);
+        assert_eq!(defaults.len(), 1);
+
+        let client = Client::tracked(rocket.register("/", defaults)).unwrap();
+        let response = client.get("/panic").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let rocket = rocket::custom(rocket::Config::debug_default())
+            .mount("/", rocket::routes![panic])
+            .register("/", rocket::catchers![custom_error]);
+        assert!(catchers(&rocket).is_empty());
+    }
+
+    #[rocket::async_test]
+    pub async fn test_panic_slot() {
+        set_panic_hook();
+
+        // Panics outside of a registered request are not kept.
+        let slot = PanicSlot::register();
+        let _ = std::panic::catch_unwind(|| panic!("not a request"));
+        assert!(slot.take().is_none());
+
+        let task = tokio::spawn(async {
+            let slot = PanicSlot::register();
+            let _ = std::panic::catch_unwind(|| panic!("handler bug"));
+            let panic = slot.take().unwrap();
+            assert_eq!(panic.message, "handler bug");
+            assert!(panic.location.contains("fairing.rs"));
+            assert!(slot.take().is_none());
+            slot.0.unwrap()
+        })
+        .await
+        .unwrap();
+
+        assert!(!panics().contains_key(&task));
+    }
+}
@@ -1,27 +1,204 @@
+pub(crate) mod cors;
+pub mod fairing;
+pub mod openapi;
+pub(crate) mod settings;
+pub mod transcoding;
 
+use std::sync::Arc;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::State;
+use serde_json::json;
+
+use crate::grpc::details::ErrorDetails;
 use crate::grpc::rpc;
+use crate::service::Service;
 
-/// Gives the default settings for a HTTP service.
-pub(crate) fn config(port: i64, name: &str) -> figment::Figment  {
-    figment::Figment::from(rocket::Config::default())
+/// Gives the settings for a HTTP service.
+pub(crate) fn config(port: u16, name: &str, settings: &settings::HttpSettings) -> figment::Figment {
+    let figment = figment::Figment::from(rocket::Config::default())
         .merge(("log_level", rocket::config::LogLevel::Off))
         .merge(("port", port))
-        .merge(("ident", name))
+        .merge(("ident", name));
+
+    settings.merge(figment)
+}
+
+/// Gives handlers access to the Service object, as a request guard:
+///
+/// ```ignore
+/// #[get("/examples/<id>")]
+/// async fn get_example(service: &Service, id: &str) -> RpcResponse<Example> { ... }
+/// ```
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Service {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, ()> {
+        request
+            .guard::<&State<Arc<Service>>>()
+            .await
+            .map(|service| service.inner().as_ref())
+    }
 }
 
 pub fn response<S: serde::Serialize>(res: &S) -> rocket::response::content::Json<String> {
     rocket::response::content::Json(serde_json::to_string(res).unwrap())
 }
 
-pub fn response_from_rpc<S: serde::Serialize>(res: rpc::Response<S>) -> rocket::response::content::Json<String> {
-    let response = match res {
-        Ok(r) => match serde_json::to_string(&r.into_inner()) {
-            Ok(r) => r,
-            Err(_) => "TODO SERIALIZE ERROR".to_string()
-        },
-        Err(e) => e.to_string()
-    };
+/// A JSON response built from an RPC method result. Errors are answered with
+/// the HTTP status matching their gRPC code and a body like:
+///
+/// ```json
+/// {"code": "NOT_FOUND", "message": "...", "details": []}
+/// ```
+#[derive(Debug)]
+pub struct RpcResponse<S>(rpc::Response<S>);
+
+pub fn response_from_rpc<S: serde::Serialize>(res: rpc::Response<S>) -> RpcResponse<S> {
+    RpcResponse(res)
+}
+
+impl<'r, 'o: 'r, S: serde::Serialize> Responder<'r, 'o> for RpcResponse<S> {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> response::Result<'o> {
+        let (status, body) = match self.0 {
+            Ok(r) => match serde_json::to_string(&r.into_inner()) {
+                Ok(body) => (Status::Ok, body),
+                Err(e) => {
+                    let status =
+                        tonic::Status::internal(format!("could not serialize response: {}", e));
+                    (Status::InternalServerError, error_body(&status))
+                }
+            },
+            Err(e) => (http_status(e.code()), error_body(&e)),
+        };
+
+        (status, (ContentType::JSON, body)).respond_to(request)
+    }
+}
+
+/// Maps a gRPC status code to its HTTP equivalent, following the mapping
+/// used by google.api HTTP gateways.
+pub fn http_status(code: tonic::Code) -> Status {
+    match code {
+        tonic::Code::Ok => Status::Ok,
+        tonic::Code::Cancelled => Status::new(499),
+        tonic::Code::Unknown => Status::InternalServerError,
+        tonic::Code::InvalidArgument => Status::BadRequest,
+        tonic::Code::DeadlineExceeded => Status::GatewayTimeout,
+        tonic::Code::NotFound => Status::NotFound,
+        tonic::Code::AlreadyExists => Status::Conflict,
+        tonic::Code::PermissionDenied => Status::Forbidden,
+        tonic::Code::ResourceExhausted => Status::TooManyRequests,
+        tonic::Code::FailedPrecondition => Status::BadRequest,
+        tonic::Code::Aborted => Status::Conflict,
+        tonic::Code::OutOfRange => Status::BadRequest,
+        tonic::Code::Unimplemented => Status::NotImplemented,
+        tonic::Code::Internal => Status::InternalServerError,
+        tonic::Code::Unavailable => Status::ServiceUnavailable,
+        tonic::Code::DataLoss => Status::InternalServerError,
+        tonic::Code::Unauthenticated => Status::Unauthorized,
+    }
+}
 
-    rocket::response::content::Json(response)
+fn code_name(code: tonic::Code) -> &'static str {
+    match code {
+        tonic::Code::Ok => "OK",
+        tonic::Code::Cancelled => "CANCELLED",
+        tonic::Code::Unknown => "UNKNOWN",
+        tonic::Code::InvalidArgument => "INVALID_ARGUMENT",
+        tonic::Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        tonic::Code::NotFound => "NOT_FOUND",
+        tonic::Code::AlreadyExists => "ALREADY_EXISTS",
+        tonic::Code::PermissionDenied => "PERMISSION_DENIED",
+        tonic::Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        tonic::Code::FailedPrecondition => "FAILED_PRECONDITION",
+        tonic::Code::Aborted => "ABORTED",
+        tonic::Code::OutOfRange => "OUT_OF_RANGE",
+        tonic::Code::Unimplemented => "UNIMPLEMENTED",
+        tonic::Code::Internal => "INTERNAL",
+        tonic::Code::Unavailable => "UNAVAILABLE",
+        tonic::Code::DataLoss => "DATA_LOSS",
+        tonic::Code::Unauthenticated => "UNAUTHENTICATED",
+    }
 }
 
+/// Builds the JSON error body for a gRPC status, including its google.rpc
+/// details in their JSON form.
+pub(crate) fn error_body(status: &tonic::Status) -> String {
+    let details = ErrorDetails::from_status(status);
+    let mut items = Vec::new();
+
+    if let Some(d) = &details.bad_request {
+        let violations: Vec<_> = d
+            .field_violations
+            .iter()
+            .map(|v| json!({"field": v.field, "description": v.description}))
+            .collect();
+
+        items.push(json!({
+            "@type": "type.googleapis.com/google.rpc.BadRequest",
+            "fieldViolations": violations,
+        }));
+    }
+
+    if let Some(d) = &details.error_info {
+        items.push(json!({
+            "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+            "reason": d.reason,
+            "domain": d.domain,
+            "metadata": d.metadata,
+        }));
+    }
+
+    if let Some(delay) = details.retry_delay() {
+        items.push(json!({
+            "@type": "type.googleapis.com/google.rpc.RetryInfo",
+            "retryDelay": format!("{}s", delay.as_secs_f64()),
+        }));
+    }
+
+    if let Some(d) = &details.localized_message {
+        items.push(json!({
+            "@type": "type.googleapis.com/google.rpc.LocalizedMessage",
+            "locale": d.locale,
+            "message": d.message,
+        }));
+    }
+
+    json!({
+        "code": code_name(status.code()),
+        "message": status.message(),
+        "details": items,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_error_body() {
+        let status = rpc::Error::new(rpc::ErrorCode::Validation, Some("invalid example"))
+            .with_field_violation("name", "must not be empty")
+            .to_status();
+
+        assert_eq!(http_status(status.code()), Status::BadRequest);
+
+        let body: serde_json::Value = serde_json::from_str(&error_body(&status)).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "code": "INVALID_ARGUMENT",
+                "message": "invalid example",
+                "details": [{
+                    "@type": "type.googleapis.com/google.rpc.BadRequest",
+                    "fieldViolations": [{"field": "name", "description": "must not be empty"}],
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,303 @@
+// OpenAPI 3 documents for HTTP services, built from rocket routes and from
+// the annotated methods of a transcoding gateway.
+
+use std::sync::Arc;
+
+use rocket::http::{ContentType, Method};
+use rocket::route::{Handler, Outcome, Route};
+use rocket::{Data, Request};
+use serde_json::{json, Map, Value};
+
+use crate::http::transcoding::Gateway;
+use crate::service::Service;
+
+/// Where the document is served.
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// Where the docs page is served.
+pub const DOCS_PATH: &str = "/docs";
+
+/// An OpenAPI document for the service API.
+///
+/// ```ignore
+/// let mut openapi = OpenApi::new(&service);
+/// openapi
+///     .with_routes("/", &routes![get_example])
+///     .with_operation("get", "/examples/{id}", json!({
+///         "summary": "Retrieves an example",
+///         "responses": {"200": {"description": "OK", "content": {"application/json": {
+///             "schema": {"$ref": "#/components/schemas/Example"}
+///         }}}},
+///     }))
+///     .with_schema("Example", json!({"type": "object", "properties": {"id": {"type": "string"}}}));
+///
+/// let rocket = rocket::custom(service.http_config())
+///     .mount("/", routes![get_example])
+///     .mount("/", openapi.routes());
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpenApi {
+    title: String,
+    version: String,
+    paths: Map<String, Value>,
+    schemas: Map<String, Value>,
+}
+
+impl OpenApi {
+    pub fn new(service: &Service) -> Self {
+        let mut schemas = Map::new();
+        schemas.insert(
+            "Error".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "code": { "type": "string", "example": "NOT_FOUND" },
+                    "message": { "type": "string" },
+                    "details": { "type": "array", "items": { "type": "object" } },
+                },
+            }),
+        );
+
+        OpenApi {
+            title: service.name().to_string(),
+            version: service.version().to_string(),
+            paths: Map::new(),
+            schemas,
+        }
+    }
+
+    /// Adds operations for rocket routes, mounted at `base`. Dynamic path
+    /// and query segments are documented as string parameters.
+    pub fn with_routes(&mut self, base: &str, routes: &[Route]) -> &mut Self {
+        for route in routes {
+            let mut parameters = Vec::new();
+            let mut path = String::new();
+
+            let uri = format!("{}{}", base.trim_end_matches('/'), route.uri.path());
+            for segment in uri.split('/').filter(|s| !s.is_empty()) {
+                match dynamic_segment(segment) {
+                    Some(name) => {
+                        path.push_str(&format!("/{{{}}}", name));
+                        parameters.push(json!({
+                            "name": name,
+                            "in": "path",
+                            "required": true,
+                            "schema": { "type": "string" },
+                        }));
+                    }
+                    None => path.push_str(&format!("/{}", segment)),
+                }
+            }
+
+            if path.is_empty() {
+                path.push('/');
+            }
+
+            for segment in route.uri.query().unwrap_or_default().split('&') {
+                if let Some(name) = dynamic_segment(segment) {
+                    parameters.push(json!({
+                        "name": name,
+                        "in": "query",
+                        "schema": { "type": "string" },
+                    }));
+                }
+            }
+
+            let mut operation = json!({
+                "parameters": parameters,
+                "responses": { "200": { "description": "OK" } },
+            });
+
+            if let Some(name) = &route.name {
+                operation["operationId"] = Value::String(name.to_string());
+            }
+
+            self.with_operation(route.method.as_str(), &path, operation);
+        }
+
+        self
+    }
+
+    /// Adds the operations of a transcoding gateway, with the schemas of all
+    /// its messages.
+    pub fn with_gateway(&mut self, gateway: &Gateway) -> &mut Self {
+        gateway.openapi(&mut self.paths, &mut self.schemas);
+        self
+    }
+
+    /// Adds or completes an operation, using the OpenAPI path syntax, like
+    /// `/examples/{id}`. Fields of an existing operation are replaced by the
+    /// ones given here.
+    pub fn with_operation(&mut self, method: &str, path: &str, operation: Value) -> &mut Self {
+        let item = self
+            .paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}));
+
+        let existing = &mut item[method.to_lowercase()];
+        match (existing.as_object_mut(), operation) {
+            (Some(existing), Value::Object(fields)) => existing.extend(fields),
+            (_, operation) => *existing = operation,
+        }
+
+        self
+    }
+
+    /// Adds a schema to the document components, to be referenced as
+    /// `#/components/schemas/<name>`.
+    pub fn with_schema(&mut self, name: &str, schema: Value) -> &mut Self {
+        self.schemas.insert(name.to_string(), schema);
+        self
+    }
+
+    /// Gives back the OpenAPI document.
+    pub fn document(&self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": { "title": self.title, "version": self.version },
+            "paths": self.paths,
+            "components": { "schemas": self.schemas },
+        })
+    }
+
+    /// Gives back the routes serving the document and the docs page.
+    pub fn routes(&self) -> Vec<Route> {
+        let document = Page {
+            content_type: ContentType::JSON,
+            body: Arc::new(self.document().to_string()),
+        };
+
+        let docs = Page {
+            content_type: ContentType::HTML,
+            body: Arc::new(DOCS_PAGE.replace("{{title}}", &html_escape(&self.title))),
+        };
+
+        vec![
+            Route::new(Method::Get, OPENAPI_PATH, document),
+            Route::new(Method::Get, DOCS_PATH, docs),
+        ]
+    }
+}
+
+/// Gives back the name of rocket dynamic segments, like `<id>` or
+/// `<path..>`.
+fn dynamic_segment(segment: &str) -> Option<&str> {
+    segment
+        .strip_prefix('<')
+        .and_then(|s| s.strip_suffix('>'))
+        .map(|s| s.trim_end_matches(".."))
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[derive(Clone)]
+struct Page {
+    content_type: ContentType,
+    body: Arc<String>,
+}
+
+#[rocket::async_trait]
+impl Handler for Page {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _: Data<'r>) -> Outcome<'r> {
+        Outcome::from(
+            request,
+            (self.content_type.clone(), self.body.as_ref().clone()),
+        )
+    }
+}
+
+const DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+<style>
+body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #222; }
+details { border: 1px solid #ddd; border-radius: 4px; margin: 0.5em 0; padding: 0.5em; }
+summary { cursor: pointer; }
+.method { display: inline-block; width: 5em; font-weight: bold; text-transform: uppercase; }
+pre { background: #f6f6f6; padding: 0.5em; overflow: auto; }
+</style>
+</head>
+<body>
+<h1>{{title}}</h1>
+<p><a href="openapi.json">openapi.json</a></p>
+<div id="operations"></div>
+<script>
+fetch("openapi.json").then(r => r.json()).then(doc => {
+  document.querySelector("h1").textContent = doc.info.title + " " + doc.info.version;
+  const root = document.getElementById("operations");
+  for (const [path, item] of Object.entries(doc.paths)) {
+    for (const [method, op] of Object.entries(item)) {
+      const details = document.createElement("details");
+      const summary = document.createElement("summary");
+      const name = document.createElement("span");
+      name.className = "method";
+      name.textContent = method;
+      summary.append(name, path + (op.summary ? " - " + op.summary : ""));
+      const body = document.createElement("pre");
+      body.textContent = JSON.stringify(op, null, 2);
+      details.append(summary, body);
+      root.append(details);
+    }
+  }
+  const schemas = document.createElement("details");
+  const title = document.createElement("summary");
+  title.textContent = "Schemas";
+  const body = document.createElement("pre");
+  body.textContent = JSON.stringify(doc.components.schemas, null, 2);
+  schemas.append(title, body);
+  root.append(schemas);
+});
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_openapi_routes() {
+        let page = Page {
+            content_type: ContentType::Plain,
+            body: Arc::new(String::new()),
+        };
+
+        let mut openapi = OpenApi {
+            title: "example".to_string(),
+            version: "0.1.0".to_string(),
+            paths: Map::new(),
+            schemas: Map::new(),
+        };
+
+        openapi
+            .with_routes(
+                "/v1",
+                &[Route::new(Method::Get, "/examples/<id>?<verbose>", page)],
+            )
+            .with_operation(
+                "GET",
+                "/v1/examples/{id}",
+                json!({"summary": "Retrieves an example"}),
+            );
+
+        let document = openapi.document();
+        let operation = &document["paths"]["/v1/examples/{id}"]["get"];
+        assert_eq!(operation["summary"], "Retrieves an example");
+        assert_eq!(
+            operation["parameters"],
+            json!([
+                {"name": "id", "in": "path", "required": true, "schema": {"type": "string"}},
+                {"name": "verbose", "in": "query", "schema": {"type": "string"}},
+            ])
+        );
+        assert_eq!(document["info"]["version"], "0.1.0");
+    }
+}
@@ -0,0 +1,199 @@
+// HTTP service settings, loaded from the `[http]` section of the settings
+// file. Environment variables take precedence over the file values.
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use rocket::data::ByteUnit;
+
+use crate::config::{vars, Config, GetEnv};
+use crate::definition::HttpDefinition;
+use crate::error::{Error, Result};
+use crate::http::cors::CorsSettings;
+
+const LIMITS_ENV_PREFIX: &str = "HTTP_LIMITS_";
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HttpSettings {
+    pub address: Option<IpAddr>,
+    pub workers: Option<usize>,
+    pub keep_alive: Option<u32>,
+    pub limits: Vec<(String, ByteUnit)>,
+    pub tls: Option<(PathBuf, PathBuf)>,
+    pub cors: Option<CorsSettings>,
+}
+
+impl HttpSettings {
+    /// Loads and validates the HTTP settings.
+    pub fn new(definition: &HttpDefinition) -> Result<Self> {
+        let address = env_or("HTTP_ADDRESS", definition.address.clone())
+            .map(|a| {
+                a.parse::<IpAddr>()
+                    .map_err(|_| invalid(format!("address '{}' is not an IP address", a)))
+            })
+            .transpose()?;
+
+        let workers = env_or("HTTP_WORKERS", definition.workers.map(|w| w.to_string()))
+            .map(|w| match w.parse::<usize>() {
+                Ok(w) if w > 0 => Ok(w),
+                _ => Err(invalid(format!(
+                    "workers '{}' must be a positive number",
+                    w
+                ))),
+            })
+            .transpose()?;
+
+        let keep_alive = env_or(
+            "HTTP_KEEP_ALIVE",
+            definition.keep_alive.map(|k| k.to_string()),
+        )
+        .map(|k| {
+            k.parse::<u32>()
+                .map_err(|_| invalid(format!("keep_alive '{}' must be a number of seconds", k)))
+        })
+        .transpose()?;
+
+        let mut limits: Vec<(String, String)> = definition
+            .limits
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        for (key, value) in std::env::vars() {
+            if let Some(name) = key.strip_prefix(LIMITS_ENV_PREFIX) {
+                let name = name.to_lowercase();
+                limits.retain(|(k, _)| *k != name);
+                limits.push((name, value));
+            }
+        }
+
+        limits.sort();
+        let limits = limits
+            .into_iter()
+            .map(|(name, value)| match value.parse::<ByteUnit>() {
+                Ok(limit) => Ok((name, limit)),
+                Err(_) => Err(invalid(format!(
+                    "limit '{}' has an invalid size '{}'",
+                    name, value
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let cert = env_or(
+            "HTTP_TLS_CERT",
+            definition.tls.as_ref().map(|t| t.cert.clone()),
+        );
+        let key = env_or(
+            "HTTP_TLS_KEY",
+            definition.tls.as_ref().map(|t| t.key.clone()),
+        );
+        let tls = match (cert, key) {
+            (None, None) => None,
+            (Some(cert), Some(key)) => {
+                for path in [&cert, &key] {
+                    if !std::path::Path::new(path).is_file() {
+                        return Err(invalid(format!("TLS file '{}' does not exist", path)));
+                    }
+                }
+
+                Some((PathBuf::from(cert), PathBuf::from(key)))
+            }
+            _ => {
+                return Err(invalid(
+                    "TLS certificate and key must be set together".to_string(),
+                ))
+            }
+        };
+
+        let cors = CorsSettings::new(&definition.cors, &vars::HTTP_CORS)?;
+
+        Ok(HttpSettings {
+            address,
+            workers,
+            keep_alive,
+            limits,
+            tls,
+            cors,
+        })
+    }
+
+    /// Applies the settings over a rocket configuration.
+    pub fn merge(&self, mut figment: figment::Figment) -> figment::Figment {
+        if let Some(address) = self.address {
+            figment = figment.merge(("address", address));
+        }
+
+        if let Some(workers) = self.workers {
+            figment = figment.merge(("workers", workers));
+        }
+
+        if let Some(keep_alive) = self.keep_alive {
+            figment = figment.merge(("keep_alive", keep_alive));
+        }
+
+        for (name, limit) in &self.limits {
+            figment = figment.merge((format!("limits.{}", name), limit));
+        }
+
+        if let Some((cert, key)) = &self.tls {
+            figment = figment.merge(("tls.certs", cert)).merge(("tls.key", key));
+        }
+
+        figment
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: Option<T>) -> Option<T> {
+    Config::get_os_env(key, default)
+}
+
+fn invalid(message: String) -> Error {
+    Error::HttpSettings(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_http_settings() {
+        let mut definition = HttpDefinition {
+            address: Some("127.0.0.1".to_string()),
+            workers: Some(2),
+            ..HttpDefinition::default()
+        };
+        definition
+            .limits
+            .insert("json".to_string(), "2MiB".to_string());
+
+        let settings = HttpSettings::new(&definition).unwrap();
+        assert_eq!(settings.address, "127.0.0.1".parse().ok());
+        assert_eq!(
+            settings.limits,
+            vec![("json".to_string(), ByteUnit::Mebibyte(2))]
+        );
+
+        let config: rocket::Config = settings
+            .merge(figment::Figment::from(rocket::Config::default()))
+            .extract()
+            .unwrap();
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.limits.get("json"), Some(ByteUnit::Mebibyte(2)));
+
+        definition
+            .limits
+            .insert("json".to_string(), "lots".to_string());
+        assert!(HttpSettings::new(&definition).is_err());
+        definition.limits.clear();
+
+        definition.address = Some("localhost:80".to_string());
+        assert!(HttpSettings::new(&definition).is_err());
+        definition.address = None;
+
+        definition.tls = Some(crate::definition::TlsDefinition {
+            cert: "/nonexistent/cert.pem".to_string(),
+            key: "/nonexistent/key.pem".to_string(),
+        });
+        assert!(HttpSettings::new(&definition).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+// Minimal protobuf descriptor types. prost-types drops the extensions found
+// in MethodOptions, so the service and method messages are redeclared here
+// to keep the google.api.http annotation (field 72295728).
+
+use std::collections::HashMap;
+
+use prost::Message;
+use prost_types::{DescriptorProto, EnumDescriptorProto};
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorSet {
+    #[prost(message, repeated, tag = "1")]
+    pub file: Vec<FileDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub package: String,
+    #[prost(message, repeated, tag = "4")]
+    pub message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "5")]
+    pub enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag = "6")]
+    pub service: Vec<ServiceDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ServiceDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub method: Vec<MethodDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub input_type: String,
+    #[prost(string, tag = "3")]
+    pub output_type: String,
+    #[prost(message, optional, tag = "4")]
+    pub options: Option<MethodOptions>,
+    #[prost(bool, tag = "5")]
+    pub client_streaming: bool,
+    #[prost(bool, tag = "6")]
+    pub server_streaming: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodOptions {
+    #[prost(message, optional, tag = "72295728")]
+    pub http: Option<HttpRule>,
+}
+
+/// google.api.HttpRule
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct HttpRule {
+    #[prost(string, tag = "1")]
+    pub selector: String,
+    #[prost(string, tag = "2")]
+    pub get: String,
+    #[prost(string, tag = "3")]
+    pub put: String,
+    #[prost(string, tag = "4")]
+    pub post: String,
+    #[prost(string, tag = "5")]
+    pub delete: String,
+    #[prost(string, tag = "6")]
+    pub patch: String,
+    #[prost(message, optional, tag = "8")]
+    pub custom: Option<CustomHttpPattern>,
+    #[prost(string, tag = "7")]
+    pub body: String,
+    #[prost(string, tag = "12")]
+    pub response_body: String,
+    #[prost(message, repeated, tag = "11")]
+    pub additional_bindings: Vec<HttpRule>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct CustomHttpPattern {
+    #[prost(string, tag = "1")]
+    pub kind: String,
+    #[prost(string, tag = "2")]
+    pub path: String,
+}
+
+impl HttpRule {
+    /// Gives back the HTTP method and path template of the rule.
+    pub fn pattern(&self) -> Option<(String, String)> {
+        let patterns = [
+            ("GET", &self.get),
+            ("PUT", &self.put),
+            ("POST", &self.post),
+            ("DELETE", &self.delete),
+            ("PATCH", &self.patch),
+        ];
+
+        if let Some((method, path)) = patterns.iter().find(|(_, p)| !p.is_empty()) {
+            return Some((method.to_string(), path.to_string()));
+        }
+
+        self.custom
+            .as_ref()
+            .map(|c| (c.kind.to_uppercase(), c.path.clone()))
+    }
+}
+
+/// A unary RPC method with its HTTP bindings.
+#[derive(Clone, Debug)]
+pub(crate) struct Method {
+    /// The gRPC path, like `/example.ExampleService/GetExample`.
+    pub path: String,
+    pub input_type: String,
+    pub output_type: String,
+    pub bindings: Vec<HttpRule>,
+}
+
+/// Indexes the messages, enums and annotated methods of a descriptor set.
+#[derive(Debug, Default)]
+pub(crate) struct DescriptorPool {
+    pub messages: HashMap<String, DescriptorProto>,
+    pub enums: HashMap<String, EnumDescriptorProto>,
+    pub methods: Vec<Method>,
+}
+
+impl DescriptorPool {
+    /// Decodes an encoded google.protobuf.FileDescriptorSet, as generated by
+    /// `protoc --include_imports --descriptor_set_out`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let set = match FileDescriptorSet::decode(bytes) {
+            Ok(set) => set,
+            Err(e) => return Err(Error::DefinitionParser(e.to_string())),
+        };
+
+        let mut pool = DescriptorPool::default();
+        for file in set.file {
+            let prefix = if file.package.is_empty() {
+                String::new()
+            } else {
+                format!(".{}", file.package)
+            };
+
+            for message in &file.message_type {
+                pool.add_message(&prefix, message);
+            }
+
+            for e in &file.enum_type {
+                pool.enums
+                    .insert(format!("{}.{}", prefix, e.name()), e.clone());
+            }
+
+            for service in &file.service {
+                for method in &service.method {
+                    pool.add_method(&file.package, service, method);
+                }
+            }
+        }
+
+        Ok(pool)
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &DescriptorProto) {
+        let name = format!("{}.{}", prefix, message.name());
+
+        for nested in &message.nested_type {
+            self.add_message(&name, nested);
+        }
+
+        for e in &message.enum_type {
+            self.enums
+                .insert(format!("{}.{}", name, e.name()), e.clone());
+        }
+
+        self.messages.insert(name, message.clone());
+    }
+
+    fn add_method(
+        &mut self,
+        package: &str,
+        service: &ServiceDescriptorProto,
+        method: &MethodDescriptorProto,
+    ) {
+        // Only unary methods can be mapped to a single HTTP request.
+        if method.client_streaming || method.server_streaming {
+            return;
+        }
+
+        let rule = match method.options.as_ref().and_then(|o| o.http.as_ref()) {
+            Some(rule) => rule,
+            None => return,
+        };
+
+        let mut bindings = vec![rule.clone()];
+        bindings.extend(rule.additional_bindings.iter().cloned());
+
+        let service_name = if package.is_empty() {
+            service.name.clone()
+        } else {
+            format!("{}.{}", package, service.name)
+        };
+
+        self.methods.push(Method {
+            path: format!("/{}/{}", service_name, method.name),
+            input_type: method.input_type.clone(),
+            output_type: method.output_type.clone(),
+            bindings,
+        });
+    }
+}
@@ -0,0 +1,961 @@
+// Conversion between JSON and protobuf wire encoded messages, driven by the
+// descriptors of the service API, following the proto3 JSON mapping.
+
+use prost::encoding::{decode_key, decode_varint, encode_key, encode_varint, WireType};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto};
+use serde_json::{Map, Number, Value};
+
+use crate::http::transcoding::descriptor::DescriptorPool;
+
+type CodecResult<T> = std::result::Result<T, String>;
+
+pub(crate) const TIMESTAMP: &str = ".google.protobuf.Timestamp";
+pub(crate) const DURATION: &str = ".google.protobuf.Duration";
+pub(crate) const FIELD_MASK: &str = ".google.protobuf.FieldMask";
+pub(crate) const EMPTY: &str = ".google.protobuf.Empty";
+pub(crate) const WRAPPERS: [&str; 9] = [
+    ".google.protobuf.DoubleValue",
+    ".google.protobuf.FloatValue",
+    ".google.protobuf.Int64Value",
+    ".google.protobuf.UInt64Value",
+    ".google.protobuf.Int32Value",
+    ".google.protobuf.UInt32Value",
+    ".google.protobuf.BoolValue",
+    ".google.protobuf.StringValue",
+    ".google.protobuf.BytesValue",
+];
+
+pub(crate) struct Codec<'a> {
+    pool: &'a DescriptorPool,
+}
+
+impl<'a> Codec<'a> {
+    pub fn new(pool: &'a DescriptorPool) -> Self {
+        Codec { pool }
+    }
+
+    /// Encodes a JSON value as a message of the given type.
+    pub fn encode(&self, type_name: &str, value: &Value) -> CodecResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.encode_message(type_name, value, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes a message of the given type into its JSON value.
+    pub fn decode(&self, type_name: &str, bytes: &[u8]) -> CodecResult<Value> {
+        self.decode_message(type_name, bytes)
+    }
+
+    fn message(&self, type_name: &str) -> CodecResult<&'a DescriptorProto> {
+        self.pool
+            .messages
+            .get(type_name)
+            .ok_or_else(|| format!("unknown message type '{}'", type_name))
+    }
+
+    fn encode_message(&self, type_name: &str, value: &Value, buf: &mut Vec<u8>) -> CodecResult<()> {
+        match type_name {
+            TIMESTAMP => return encode_seconds_nanos(parse_timestamp(as_str(value)?)?, buf),
+            DURATION => return encode_seconds_nanos(parse_duration(as_str(value)?)?, buf),
+            FIELD_MASK => {
+                for path in as_str(value)?.split(',').filter(|p| !p.is_empty()) {
+                    encode_key(1, WireType::LengthDelimited, buf);
+                    encode_bytes(snake_case(path).as_bytes(), buf);
+                }
+                return Ok(());
+            }
+            t if WRAPPERS.contains(&t) => {
+                let field = self.field_by_number(self.message(t)?, 1)?;
+                return self.encode_value(field, value, buf);
+            }
+            _ => {}
+        }
+
+        let descriptor = self.message(type_name)?;
+        let object = match value {
+            Value::Object(o) => o,
+            Value::Null => return Ok(()),
+            _ => return Err(format!("expected an object for '{}'", type_name)),
+        };
+
+        for (key, value) in object {
+            let field = descriptor
+                .field
+                .iter()
+                .find(|f| f.json_name() == key || f.name() == key)
+                .ok_or_else(|| format!("unknown field '{}' in '{}'", key, type_name))?;
+
+            if value.is_null() {
+                continue;
+            }
+
+            if let Some(entry) = self.map_entry(field)? {
+                self.encode_map(field, entry, value, buf)?;
+            } else if field.label() == Label::Repeated {
+                match value {
+                    Value::Array(items) => {
+                        for item in items {
+                            self.encode_value(field, item, buf)?;
+                        }
+                    }
+                    item => self.encode_value(field, item, buf)?,
+                }
+            } else {
+                self.encode_value(field, value, buf)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_map(
+        &self,
+        field: &FieldDescriptorProto,
+        entry: &DescriptorProto,
+        value: &Value,
+        buf: &mut Vec<u8>,
+    ) -> CodecResult<()> {
+        let object = match value {
+            Value::Object(o) => o,
+            _ => return Err(format!("expected an object for map '{}'", field.name())),
+        };
+
+        let key_field = self.field_by_number(entry, 1)?;
+        let value_field = self.field_by_number(entry, 2)?;
+
+        for (key, value) in object {
+            let mut item = Vec::new();
+            self.encode_value(key_field, &Value::String(key.clone()), &mut item)?;
+            self.encode_value(value_field, value, &mut item)?;
+
+            encode_key(field.number() as u32, WireType::LengthDelimited, buf);
+            encode_bytes(&item, buf);
+        }
+
+        Ok(())
+    }
+
+    fn encode_value(
+        &self,
+        field: &FieldDescriptorProto,
+        value: &Value,
+        buf: &mut Vec<u8>,
+    ) -> CodecResult<()> {
+        let tag = field.number() as u32;
+        let name = field.name();
+
+        match field.r#type() {
+            Type::Double => {
+                encode_key(tag, WireType::SixtyFourBit, buf);
+                buf.extend_from_slice(&as_f64(value, name)?.to_le_bytes());
+            }
+            Type::Float => {
+                encode_key(tag, WireType::ThirtyTwoBit, buf);
+                buf.extend_from_slice(&(as_f64(value, name)? as f32).to_le_bytes());
+            }
+            Type::Int64 | Type::Int32 => {
+                encode_key(tag, WireType::Varint, buf);
+                encode_varint(as_i64(value, name)? as u64, buf);
+            }
+            Type::Uint64 | Type::Uint32 => {
+                encode_key(tag, WireType::Varint, buf);
+                encode_varint(as_u64(value, name)?, buf);
+            }
+            Type::Sint64 | Type::Sint32 => {
+                let v = as_i64(value, name)?;
+                encode_key(tag, WireType::Varint, buf);
+                encode_varint(((v << 1) ^ (v >> 63)) as u64, buf);
+            }
+            Type::Fixed64 => {
+                encode_key(tag, WireType::SixtyFourBit, buf);
+                buf.extend_from_slice(&as_u64(value, name)?.to_le_bytes());
+            }
+            Type::Sfixed64 => {
+                encode_key(tag, WireType::SixtyFourBit, buf);
+                buf.extend_from_slice(&as_i64(value, name)?.to_le_bytes());
+            }
+            Type::Fixed32 => {
+                encode_key(tag, WireType::ThirtyTwoBit, buf);
+                buf.extend_from_slice(&(as_u64(value, name)? as u32).to_le_bytes());
+            }
+            Type::Sfixed32 => {
+                encode_key(tag, WireType::ThirtyTwoBit, buf);
+                buf.extend_from_slice(&(as_i64(value, name)? as i32).to_le_bytes());
+            }
+            Type::Bool => {
+                let v = match value {
+                    Value::Bool(b) => *b,
+                    Value::String(s) if s == "true" => true,
+                    Value::String(s) if s == "false" => false,
+                    _ => return Err(format!("expected a boolean for '{}'", name)),
+                };
+
+                encode_key(tag, WireType::Varint, buf);
+                encode_varint(v as u64, buf);
+            }
+            Type::String => {
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_bytes(as_str(value)?.as_bytes(), buf);
+            }
+            Type::Bytes => {
+                let s = as_str(value)?;
+                let bytes = base64::decode(s)
+                    .or_else(|_| base64::decode_config(s, base64::URL_SAFE))
+                    .map_err(|_| format!("invalid base64 value for '{}'", name))?;
+
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_bytes(&bytes, buf);
+            }
+            Type::Enum => {
+                let number = self.enum_number(field.type_name(), value)?;
+                encode_key(tag, WireType::Varint, buf);
+                encode_varint(number as i64 as u64, buf);
+            }
+            Type::Message => {
+                let mut inner = Vec::new();
+                self.encode_message(field.type_name(), value, &mut inner)?;
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_bytes(&inner, buf);
+            }
+            Type::Group => return Err(format!("groups are not supported ('{}')", name)),
+        }
+
+        Ok(())
+    }
+
+    fn enum_number(&self, type_name: &str, value: &Value) -> CodecResult<i32> {
+        let descriptor = self
+            .pool
+            .enums
+            .get(type_name)
+            .ok_or_else(|| format!("unknown enum type '{}'", type_name))?;
+
+        match value {
+            Value::String(s) => descriptor
+                .value
+                .iter()
+                .find(|v| v.name() == s)
+                .map(|v| v.number())
+                .or_else(|| s.parse().ok())
+                .ok_or_else(|| format!("unknown value '{}' for enum '{}'", s, type_name)),
+            Value::Number(n) => n
+                .as_i64()
+                .map(|n| n as i32)
+                .ok_or_else(|| format!("invalid value for enum '{}'", type_name)),
+            _ => Err(format!("invalid value for enum '{}'", type_name)),
+        }
+    }
+
+    fn field_by_number(
+        &self,
+        descriptor: &'a DescriptorProto,
+        number: i32,
+    ) -> CodecResult<&'a FieldDescriptorProto> {
+        descriptor
+            .field
+            .iter()
+            .find(|f| f.number() == number)
+            .ok_or_else(|| format!("missing field {} in '{}'", number, descriptor.name()))
+    }
+
+    fn map_entry(&self, field: &FieldDescriptorProto) -> CodecResult<Option<&'a DescriptorProto>> {
+        if field.label() != Label::Repeated || field.r#type() != Type::Message {
+            return Ok(None);
+        }
+
+        let message = self.message(field.type_name())?;
+        let is_map = message
+            .options
+            .as_ref()
+            .map(|o| o.map_entry())
+            .unwrap_or(false);
+
+        Ok(if is_map { Some(message) } else { None })
+    }
+
+    fn decode_message(&self, type_name: &str, mut bytes: &[u8]) -> CodecResult<Value> {
+        match type_name {
+            TIMESTAMP => {
+                let (seconds, nanos) = decode_seconds_nanos(bytes)?;
+                return Ok(Value::String(format_timestamp(seconds, nanos)?));
+            }
+            DURATION => {
+                let (seconds, nanos) = decode_seconds_nanos(bytes)?;
+                return Ok(Value::String(format_duration(seconds, nanos)));
+            }
+            FIELD_MASK => {
+                let mut paths = Vec::new();
+                while !bytes.is_empty() {
+                    let (_, wire_type) = decode_key(&mut bytes).map_err(|e| e.to_string())?;
+                    let value = read_field(wire_type, &mut bytes)?;
+                    paths.push(camel_case(&String::from_utf8_lossy(value)));
+                }
+                return Ok(Value::String(paths.join(",")));
+            }
+            EMPTY => return Ok(Value::Object(Map::new())),
+            t if WRAPPERS.contains(&t) => {
+                let object = self.decode_object(self.message(t)?, bytes)?;
+                return Ok(object.get("value").cloned().unwrap_or(Value::Null));
+            }
+            _ => {}
+        }
+
+        self.decode_object(self.message(type_name)?, bytes)
+    }
+
+    fn decode_object(&self, descriptor: &DescriptorProto, mut bytes: &[u8]) -> CodecResult<Value> {
+        let mut object = Map::new();
+
+        while !bytes.is_empty() {
+            let (tag, wire_type) = decode_key(&mut bytes).map_err(|e| e.to_string())?;
+            let data = read_field(wire_type, &mut bytes)?;
+            let field = match descriptor.field.iter().find(|f| f.number() as u32 == tag) {
+                Some(f) => f,
+                None => continue,
+            };
+
+            let key = json_name(field);
+
+            if let Some(entry) = self.map_entry(field)? {
+                let (k, v) = self.decode_map_entry(entry, data)?;
+                let map = object
+                    .entry(key)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Value::Object(map) = map {
+                    map.insert(k, v);
+                }
+            } else if field.label() == Label::Repeated {
+                let values = self.decode_values(field, wire_type, data)?;
+                let list = object
+                    .entry(key)
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(list) = list {
+                    list.extend(values);
+                }
+            } else {
+                let value = self.decode_value(field, wire_type, data)?;
+                object.insert(key, value);
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+
+    fn decode_map_entry(
+        &self,
+        entry: &DescriptorProto,
+        bytes: &[u8],
+    ) -> CodecResult<(String, Value)> {
+        let key_field = self.field_by_number(entry, 1)?;
+        let value_field = self.field_by_number(entry, 2)?;
+
+        let mut key = Value::String(String::new());
+        let mut value = Value::Null;
+        let mut bytes = bytes;
+
+        while !bytes.is_empty() {
+            let (tag, wire_type) = decode_key(&mut bytes).map_err(|e| e.to_string())?;
+            let data = read_field(wire_type, &mut bytes)?;
+            match tag {
+                1 => key = self.decode_value(key_field, wire_type, data)?,
+                2 => value = self.decode_value(value_field, wire_type, data)?,
+                _ => {}
+            }
+        }
+
+        let key = match key {
+            Value::String(s) => s,
+            other => other.to_string(),
+        };
+
+        Ok((key, value))
+    }
+
+    /// Decodes the values of a repeated field, which may be packed.
+    fn decode_values(
+        &self,
+        field: &FieldDescriptorProto,
+        wire_type: WireType,
+        data: &[u8],
+    ) -> CodecResult<Vec<Value>> {
+        let packable = !matches!(field.r#type(), Type::String | Type::Bytes | Type::Message);
+        if !(packable && wire_type == WireType::LengthDelimited) {
+            return Ok(vec![self.decode_value(field, wire_type, data)?]);
+        }
+
+        let item_wire_type = match field.r#type() {
+            Type::Double | Type::Fixed64 | Type::Sfixed64 => WireType::SixtyFourBit,
+            Type::Float | Type::Fixed32 | Type::Sfixed32 => WireType::ThirtyTwoBit,
+            _ => WireType::Varint,
+        };
+
+        let mut values = Vec::new();
+        let mut bytes = data;
+        while !bytes.is_empty() {
+            let item = read_field(item_wire_type, &mut bytes)?;
+            values.push(self.decode_value(field, item_wire_type, item)?);
+        }
+
+        Ok(values)
+    }
+
+    fn decode_value(
+        &self,
+        field: &FieldDescriptorProto,
+        wire_type: WireType,
+        data: &[u8],
+    ) -> CodecResult<Value> {
+        let varint = || -> CodecResult<u64> {
+            if wire_type != WireType::Varint {
+                return Err(format!("invalid wire type for '{}'", field.name()));
+            }
+            decode_varint(&mut &data[..]).map_err(|e| e.to_string())
+        };
+
+        let value = match field.r#type() {
+            Type::Double => float_value(f64::from_le_bytes(fixed(data)?)),
+            Type::Float => float_value(f32::from_le_bytes(fixed(data)?) as f64),
+            Type::Int64 => Value::String((varint()? as i64).to_string()),
+            Type::Uint64 => Value::String(varint()?.to_string()),
+            Type::Int32 => Value::from(varint()? as i32),
+            Type::Uint32 => Value::from(varint()? as u32),
+            Type::Sint64 => {
+                let v = varint()?;
+                Value::String((((v >> 1) as i64) ^ -((v & 1) as i64)).to_string())
+            }
+            Type::Sint32 => {
+                let v = varint()?;
+                Value::from((((v >> 1) as i64) ^ -((v & 1) as i64)) as i32)
+            }
+            Type::Fixed64 => Value::String(u64::from_le_bytes(fixed(data)?).to_string()),
+            Type::Sfixed64 => Value::String(i64::from_le_bytes(fixed(data)?).to_string()),
+            Type::Fixed32 => Value::from(u32::from_le_bytes(fixed(data)?)),
+            Type::Sfixed32 => Value::from(i32::from_le_bytes(fixed(data)?)),
+            Type::Bool => Value::Bool(varint()? != 0),
+            Type::String => Value::String(String::from_utf8_lossy(data).to_string()),
+            Type::Bytes => Value::String(base64::encode(data)),
+            Type::Enum => {
+                let number = varint()? as i32;
+                self.pool
+                    .enums
+                    .get(field.type_name())
+                    .and_then(|e| e.value.iter().find(|v| v.number() == number))
+                    .map(|v| Value::String(v.name().to_string()))
+                    .unwrap_or_else(|| Value::from(number))
+            }
+            Type::Message => self.decode_message(field.type_name(), data)?,
+            Type::Group => return Err(format!("groups are not supported ('{}')", field.name())),
+        };
+
+        Ok(value)
+    }
+}
+
+pub(crate) fn json_name(field: &FieldDescriptorProto) -> String {
+    if field.json_name().is_empty() {
+        camel_case(field.name())
+    } else {
+        field.json_name().to_string()
+    }
+}
+
+pub(crate) fn camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 4);
+
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            result.push('_');
+            result.push(c.to_ascii_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Reads the content of a field, giving back the varint bytes, the fixed
+/// size bytes or the length delimited payload.
+fn read_field<'b>(wire_type: WireType, bytes: &mut &'b [u8]) -> CodecResult<&'b [u8]> {
+    let (start, len) = match wire_type {
+        WireType::Varint => {
+            let len = bytes
+                .iter()
+                .position(|b| b & 0x80 == 0)
+                .ok_or("truncated varint")?;
+            (0, len + 1)
+        }
+        WireType::SixtyFourBit => (0, 8),
+        WireType::ThirtyTwoBit => (0, 4),
+        WireType::LengthDelimited => {
+            let mut cursor = *bytes;
+            let len = decode_varint(&mut cursor).map_err(|e| e.to_string())?;
+            let len = usize::try_from(len).map_err(|_| "truncated message")?;
+            (bytes.len() - cursor.len(), len)
+        }
+        WireType::StartGroup | WireType::EndGroup => return Err("groups are not supported".into()),
+    };
+
+    let end = start
+        .checked_add(len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or("truncated message")?;
+
+    let data = &bytes[start..end];
+    *bytes = &bytes[end..];
+    Ok(data)
+}
+
+fn fixed<const N: usize>(data: &[u8]) -> CodecResult<[u8; N]> {
+    data.try_into()
+        .map_err(|_| "invalid fixed size value".to_string())
+}
+
+fn float_value(v: f64) -> Value {
+    match Number::from_f64(v) {
+        Some(n) => Value::Number(n),
+        None if v.is_nan() => Value::String("NaN".to_string()),
+        None if v > 0.0 => Value::String("Infinity".to_string()),
+        None => Value::String("-Infinity".to_string()),
+    }
+}
+
+fn as_str(value: &Value) -> CodecResult<&str> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected a string, found '{}'", value))
+}
+
+fn as_f64(value: &Value, name: &str) -> CodecResult<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        },
+        _ => None,
+    }
+    .ok_or_else(|| format!("expected a number for '{}'", name))
+}
+
+fn as_i64(value: &Value, name: &str) -> CodecResult<i64> {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64)),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("expected an integer for '{}'", name))
+}
+
+fn as_u64(value: &Value, name: &str) -> CodecResult<u64> {
+    match value {
+        Value::Number(n) => n.as_u64().or_else(|| {
+            n.as_f64()
+                .filter(|f| f.fract() == 0.0 && *f >= 0.0)
+                .map(|f| f as u64)
+        }),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("expected an unsigned integer for '{}'", name))
+}
+
+fn encode_seconds_nanos((seconds, nanos): (i64, i32), buf: &mut Vec<u8>) -> CodecResult<()> {
+    if seconds != 0 {
+        encode_key(1, WireType::Varint, buf);
+        encode_varint(seconds as u64, buf);
+    }
+    if nanos != 0 {
+        encode_key(2, WireType::Varint, buf);
+        encode_varint(nanos as i64 as u64, buf);
+    }
+
+    Ok(())
+}
+
+fn decode_seconds_nanos(mut bytes: &[u8]) -> CodecResult<(i64, i32)> {
+    let (mut seconds, mut nanos) = (0, 0);
+
+    while !bytes.is_empty() {
+        let (tag, wire_type) = decode_key(&mut bytes).map_err(|e| e.to_string())?;
+        let mut data = read_field(wire_type, &mut bytes)?;
+        match (tag, wire_type) {
+            (1, WireType::Varint) => {
+                seconds = decode_varint(&mut data).map_err(|e| e.to_string())? as i64
+            }
+            (2, WireType::Varint) => {
+                nanos = decode_varint(&mut data).map_err(|e| e.to_string())? as i32
+            }
+            _ => {}
+        }
+    }
+
+    Ok((seconds, nanos))
+}
+
+fn parse_nanos(fraction: &str) -> CodecResult<i32> {
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid fraction '{}'", fraction));
+    }
+
+    Ok(format!("{:0<9}", fraction)
+        .parse::<i32>()
+        .unwrap_or_default())
+}
+
+fn format_nanos(nanos: i32) -> String {
+    match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    }
+}
+
+/// Parses durations like `1.5s` or `-3s`.
+fn parse_duration(value: &str) -> CodecResult<(i64, i32)> {
+    let invalid = || format!("invalid duration '{}'", value);
+    let number = value.strip_suffix('s').ok_or_else(invalid)?;
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(n) => (true, n),
+        None => (false, number),
+    };
+
+    let (seconds, nanos) = match number.split_once('.') {
+        Some((s, f)) => (s, parse_nanos(f).map_err(|_| invalid())?),
+        None => (number, 0),
+    };
+
+    let seconds: i64 = seconds.parse().map_err(|_| invalid())?;
+    Ok(if negative {
+        (-seconds, -nanos)
+    } else {
+        (seconds, nanos)
+    })
+}
+
+fn format_duration(seconds: i64, nanos: i32) -> String {
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    format!("{}{}{}s", sign, seconds.abs(), format_nanos(nanos.abs()))
+}
+
+/// Days since 1970-01-01 of a civil date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Parses RFC 3339 timestamps, like `2022-01-31T10:20:30.5Z` or
+/// `2022-01-31T07:20:30-03:00`.
+fn parse_timestamp(value: &str) -> CodecResult<(i64, i32)> {
+    let invalid = || format!("invalid timestamp '{}'", value);
+    let number = |s: &str| s.parse::<i64>().map_err(|_| invalid());
+
+    if value.len() < 20 || !value.is_ascii() {
+        return Err(invalid());
+    }
+
+    let (date, rest) = value.split_at(10);
+    let (year, month, day) = match date.split('-').collect::<Vec<_>>().as_slice() {
+        [y, m, d] => (number(y)?, number(m)?, number(d)?),
+        _ => return Err(invalid()),
+    };
+
+    let rest = rest
+        .strip_prefix(|c| c == 'T' || c == 't')
+        .ok_or_else(invalid)?;
+    let (time, rest) = rest.split_at(8);
+    let (hour, minute, second) = match time.split(':').collect::<Vec<_>>().as_slice() {
+        [h, m, s] => (number(h)?, number(m)?, number(s)?),
+        _ => return Err(invalid()),
+    };
+
+    let offset_at = rest.find(['Z', 'z', '+', '-']).ok_or_else(invalid)?;
+    let (fraction, offset) = rest.split_at(offset_at);
+    let nanos = match fraction.strip_prefix('.') {
+        Some(f) => parse_nanos(f).map_err(|_| invalid())?,
+        None if fraction.is_empty() => 0,
+        None => return Err(invalid()),
+    };
+
+    let offset = match offset {
+        "Z" | "z" => 0,
+        o if o.len() == 6 && &o[3..4] == ":" => {
+            let minutes = number(&o[1..3])? * 60 + number(&o[4..6])?;
+            if o.starts_with('-') {
+                -minutes * 60
+            } else {
+                minutes * 60
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+
+    Ok((seconds, nanos))
+}
+
+fn format_timestamp(seconds: i64, nanos: i32) -> CodecResult<String> {
+    if !(0..1_000_000_000).contains(&nanos) {
+        return Err("invalid timestamp nanos".to_string());
+    }
+
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+
+    Ok(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        format_nanos(nanos)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use prost_types::{EnumDescriptorProto, EnumValueDescriptorProto};
+    use serde_json::json;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Example {
+        #[prost(string, tag = "1")]
+        id: String,
+        #[prost(int64, tag = "2")]
+        count: i64,
+        #[prost(sint32, repeated, tag = "3")]
+        scores: Vec<i32>,
+        #[prost(enumeration = "Kind", tag = "4")]
+        kind: i32,
+        #[prost(message, optional, tag = "5")]
+        created_at: Option<prost_types::Timestamp>,
+        #[prost(map = "string, string", tag = "6")]
+        labels: std::collections::HashMap<String, String>,
+        #[prost(bytes = "vec", tag = "7")]
+        payload: Vec<u8>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+    enum Kind {
+        Unknown = 0,
+        Simple = 1,
+    }
+
+    fn field(
+        name: &str,
+        number: i32,
+        label: Label,
+        kind: Type,
+        type_name: &str,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(label as i32),
+            r#type: Some(kind as i32),
+            type_name: Some(type_name.to_string()),
+            json_name: Some(camel_case(name)),
+            ..FieldDescriptorProto::default()
+        }
+    }
+
+    fn pool() -> DescriptorPool {
+        let mut pool = DescriptorPool::default();
+        let entry = DescriptorProto {
+            name: Some("LabelsEntry".to_string()),
+            field: vec![
+                field("key", 1, Label::Optional, Type::String, ""),
+                field("value", 2, Label::Optional, Type::String, ""),
+            ],
+            options: Some(prost_types::MessageOptions {
+                map_entry: Some(true),
+                ..prost_types::MessageOptions::default()
+            }),
+            ..DescriptorProto::default()
+        };
+
+        pool.messages.insert(
+            ".example.Example".to_string(),
+            DescriptorProto {
+                name: Some("Example".to_string()),
+                field: vec![
+                    field("id", 1, Label::Optional, Type::String, ""),
+                    field("count", 2, Label::Optional, Type::Int64, ""),
+                    field("scores", 3, Label::Repeated, Type::Sint32, ""),
+                    field("kind", 4, Label::Optional, Type::Enum, ".example.Kind"),
+                    field("created_at", 5, Label::Optional, Type::Message, TIMESTAMP),
+                    field(
+                        "labels",
+                        6,
+                        Label::Repeated,
+                        Type::Message,
+                        ".example.Example.LabelsEntry",
+                    ),
+                    field("payload", 7, Label::Optional, Type::Bytes, ""),
+                ],
+                ..DescriptorProto::default()
+            },
+        );
+
+        pool.messages
+            .insert(".example.Example.LabelsEntry".to_string(), entry);
+
+        pool.enums.insert(
+            ".example.Kind".to_string(),
+            EnumDescriptorProto {
+                name: Some("Kind".to_string()),
+                value: vec![
+                    EnumValueDescriptorProto {
+                        name: Some("UNKNOWN".to_string()),
+                        number: Some(0),
+                        ..EnumValueDescriptorProto::default()
+                    },
+                    EnumValueDescriptorProto {
+                        name: Some("SIMPLE".to_string()),
+                        number: Some(1),
+                        ..EnumValueDescriptorProto::default()
+                    },
+                ],
+                ..EnumDescriptorProto::default()
+            },
+        );
+
+        pool
+    }
+
+    #[test]
+    pub fn test_json_codec() {
+        let pool = pool();
+        let codec = Codec::new(&pool);
+        let value = json!({
+            "id": "ex_1",
+            "count": "9007199254740993",
+            "scores": [1, -2, 3],
+            "kind": "SIMPLE",
+            "createdAt": "2022-01-31T10:20:30.500Z",
+            "labels": {"team": "core"},
+            "payload": "aGVsbG8=",
+        });
+
+        let bytes = codec.encode(".example.Example", &value).unwrap();
+        let example = Example::decode(bytes.as_slice()).unwrap();
+        assert_eq!(example.id, "ex_1");
+        assert_eq!(example.count, 9007199254740993);
+        assert_eq!(example.scores, vec![1, -2, 3]);
+        assert_eq!(example.kind, Kind::Simple as i32);
+        assert_eq!(
+            example.created_at,
+            Some(prost_types::Timestamp {
+                seconds: 1643624430,
+                nanos: 500_000_000,
+            })
+        );
+        assert_eq!(example.labels.get("team").map(|s| s.as_str()), Some("core"));
+        assert_eq!(example.payload, b"hello");
+
+        // prost packs repeated scalars, which must be decoded as well.
+        let decoded = codec
+            .decode(".example.Example", &example.encode_to_vec())
+            .unwrap();
+        assert_eq!(decoded, value);
+
+        assert!(codec
+            .encode(".example.Example", &json!({"unknown": 1}))
+            .is_err());
+        assert!(codec
+            .encode(".example.Example", &json!({"count": "x"}))
+            .is_err());
+
+        // A length past the end of the message, or overflowing, is an error.
+        let mut malformed = vec![0x0a];
+        encode_varint(u64::MAX, &mut malformed);
+        assert!(codec.decode(".example.Example", &malformed).is_err());
+        assert!(codec
+            .decode(".example.Example", &[0x0a, 0x05, b'a'])
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_well_known_formats() {
+        assert_eq!(parse_duration("1.5s").unwrap(), (1, 500_000_000));
+        assert_eq!(parse_duration("-3s").unwrap(), (-3, 0));
+        assert!(parse_duration("3").is_err());
+        assert_eq!(format_duration(-1, -500_000_000), "-1.500s");
+
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z").unwrap(), (0, 0));
+        assert_eq!(
+            parse_timestamp("2022-01-31T07:20:30-03:00").unwrap(),
+            (1643624430, 0)
+        );
+        assert!(parse_timestamp("2022-13-31T07:20:30Z").is_err());
+        assert_eq!(
+            format_timestamp(1643624430, 1000).unwrap(),
+            "2022-01-31T10:20:30.000001Z"
+        );
+        assert_eq!(format_timestamp(-1, 0).unwrap(), "1969-12-31T23:59:59Z");
+
+        assert_eq!(snake_case("updateMask"), "update_mask");
+        assert_eq!(camel_case("update_mask"), "updateMask");
+    }
+}
@@ -0,0 +1,571 @@
+// An HTTP/JSON gateway for gRPC services. It reads the google.api.http
+// annotations of the service API, from its descriptor set, and translates
+// every matching HTTP request into an RPC call to the gRPC server, answering
+// with the JSON mapping of the RPC response.
+
+mod descriptor;
+mod json;
+mod schema;
+mod template;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::future::{poll_fn, BoxFuture};
+use http_body::Body as HttpBody;
+use rocket::http::{ContentType, Status};
+use rocket::route::{Handler, Outcome, Route};
+use rocket::{Data, Request};
+use serde_json::{json, Map, Value};
+use tonic::body::BoxBody;
+use tonic::transport::{Body, NamedService};
+use tower::Service as _;
+
+use crate::error::{Error, Result};
+use crate::http::{error_body, http_status};
+use crate::service::Service;
+use descriptor::{DescriptorPool, HttpRule};
+use json::Codec;
+use template::PathTemplate;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type GrpcCall = Arc<
+    dyn Fn(
+            http::Request<Body>,
+        ) -> BoxFuture<'static, std::result::Result<http::Response<BoxBody>, BoxError>>
+        + Send
+        + Sync,
+>;
+
+/// The rank of the gateway routes, so that routes mounted by the service
+/// itself are always tried first.
+const GATEWAY_RANK: isize = 100;
+
+/// Transcodes HTTP/JSON requests into calls to a gRPC server, following the
+/// google.api.http annotations of its methods.
+///
+/// ```ignore
+/// const DESCRIPTOR: &[u8] = include_bytes!("../proto/descriptor.bin");
+///
+/// let gateway = Gateway::new(&service, ExampleServiceServer::new(server), DESCRIPTOR)?;
+/// let rocket = rocket::custom(service.http_config()).mount("/", gateway);
+///
+/// Service::serve_as_http(&service, rocket).await
+/// ```
+#[derive(Clone)]
+pub struct Gateway {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    pool: DescriptorPool,
+    bindings: Vec<Binding>,
+    call: GrpcCall,
+}
+
+struct Binding {
+    method: rocket::http::Method,
+    template: PathTemplate,
+    body: String,
+    response_body: String,
+    grpc_path: String,
+    input_type: String,
+    output_type: String,
+}
+
+impl Gateway {
+    /// Creates a gateway for a gRPC server. The descriptor set must be an
+    /// encoded google.protobuf.FileDescriptorSet with all imports included,
+    /// as generated by `protoc --include_imports --descriptor_set_out`.
+    pub fn new<S>(service: &Arc<Service>, grpc_server: S, descriptor_set: &[u8]) -> Result<Self>
+    where
+        S: tower_service::Service<http::Request<Body>, Response = http::Response<BoxBody>>
+            + NamedService
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError> + Send,
+    {
+        let pool = DescriptorPool::decode(descriptor_set)?;
+        let mut bindings = Vec::new();
+
+        for method in &pool.methods {
+            for rule in &method.bindings {
+                bindings.push(Binding::new(rule, method)?);
+            }
+        }
+
+        // Requests go through the same layers as the ones of gRPC clients,
+        // so RPC methods get the service and their deadlines.
+        let grpc_server = Service::grpc_layers(service).service(grpc_server);
+        let call: GrpcCall = Arc::new(move |request| {
+            let mut server = grpc_server.clone();
+            Box::pin(async move {
+                poll_fn(|cx| server.poll_ready(cx))
+                    .await
+                    .map_err(Into::into)?;
+                server.call(request).await.map_err(Into::into)
+            })
+        });
+
+        Ok(Gateway {
+            inner: Arc::new(Inner {
+                pool,
+                bindings,
+                call,
+            }),
+        })
+    }
+
+    /// Adds the gateway operations, and the schemas of the API messages, to
+    /// an OpenAPI document.
+    pub(crate) fn openapi(&self, paths: &mut Map<String, Value>, schemas: &mut Map<String, Value>) {
+        let pool = &self.inner.pool;
+        let mut operation_ids: HashMap<&str, usize> = HashMap::new();
+
+        schemas.extend(schema::schemas(pool));
+
+        for binding in &self.inner.bindings {
+            let name = binding.grpc_path.rsplit('/').next().unwrap_or_default();
+            let count = operation_ids.entry(name).or_insert(0);
+            let operation_id = match *count {
+                0 => name.to_string(),
+                n => format!("{}{}", name, n),
+            };
+            *count += 1;
+
+            let mut operation = binding.operation(pool);
+            operation["operationId"] = Value::String(operation_id);
+
+            let path = paths
+                .entry(binding.template.openapi_path())
+                .or_insert_with(|| json!({}));
+            path[binding.method.as_str().to_lowercase()] = operation;
+        }
+    }
+
+    async fn transcode(
+        &self,
+        binding: &Binding,
+        request: Value,
+        headers: http::HeaderMap,
+    ) -> (Status, String) {
+        match self.call(binding, request, headers).await {
+            Ok(body) => (Status::Ok, body.to_string()),
+            Err(status) => (http_status(status.code()), error_body(&status)),
+        }
+    }
+
+    async fn call(
+        &self,
+        binding: &Binding,
+        request: Value,
+        headers: http::HeaderMap,
+    ) -> std::result::Result<Value, tonic::Status> {
+        let codec = Codec::new(&self.inner.pool);
+        let message = codec
+            .encode(&binding.input_type, &request)
+            .map_err(tonic::Status::invalid_argument)?;
+
+        // A single uncompressed gRPC frame.
+        let mut frame = Vec::with_capacity(message.len() + 5);
+        frame.push(0);
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&message);
+
+        let mut grpc_request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(binding.grpc_path.as_str())
+            .header(http::header::CONTENT_TYPE, "application/grpc")
+            .header(http::header::TE, "trailers")
+            .body(Body::from(frame))
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        grpc_request.headers_mut().extend(headers);
+
+        let response = (self.inner.call)(grpc_request)
+            .await
+            .map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+
+        // Errors may come as a trailers only response.
+        if let Some(status) = tonic::Status::from_header_map(response.headers()) {
+            if status.code() != tonic::Code::Ok {
+                return Err(status);
+            }
+        }
+
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk?);
+        }
+
+        if let Some(trailers) = body.trailers().await? {
+            if let Some(status) = tonic::Status::from_header_map(&trailers) {
+                if status.code() != tonic::Code::Ok {
+                    return Err(status);
+                }
+            }
+        }
+
+        if data.len() < 5 || data[0] != 0 {
+            return Err(tonic::Status::internal("invalid gRPC response frame"));
+        }
+
+        let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+        let message = data
+            .get(5..5 + len)
+            .ok_or_else(|| tonic::Status::internal("truncated gRPC response frame"))?;
+
+        let response = codec
+            .decode(&binding.output_type, message)
+            .map_err(tonic::Status::internal)?;
+
+        if binding.response_body.is_empty() {
+            return Ok(response);
+        }
+
+        Ok(response
+            .get(json::camel_case(&binding.response_body))
+            .cloned()
+            .unwrap_or(Value::Null))
+    }
+}
+
+impl Binding {
+    fn new(rule: &HttpRule, method: &descriptor::Method) -> Result<Self> {
+        let (verb, path) = match rule.pattern() {
+            Some(pattern) => pattern,
+            None => {
+                return Err(Error::DefinitionParser(format!(
+                    "method '{}' has an empty HTTP rule",
+                    method.path
+                )))
+            }
+        };
+
+        let http_method = rocket::http::Method::from_str(&verb)
+            .map_err(|_| Error::DefinitionParser(format!("unsupported HTTP method '{}'", verb)))?;
+
+        Ok(Binding {
+            method: http_method,
+            template: PathTemplate::parse(&path).map_err(Error::DefinitionParser)?,
+            body: rule.body.clone(),
+            response_body: rule.response_body.clone(),
+            grpc_path: method.path.clone(),
+            input_type: method.input_type.clone(),
+            output_type: method.output_type.clone(),
+        })
+    }
+
+    fn operation(&self, pool: &DescriptorPool) -> Value {
+        let variables = self.template.variables();
+        let mut parameters: Vec<Value> = variables
+            .iter()
+            .map(|v| {
+                json!({
+                    "name": v,
+                    "in": "path",
+                    "required": true,
+                    "schema": schema::field_path_schema(pool, &self.input_type, v),
+                })
+            })
+            .collect();
+
+        // Fields not bound by the path or the body can be given as query
+        // parameters.
+        if self.body != "*" {
+            if let Some(input) = pool.messages.get(&self.input_type) {
+                for field in &input.field {
+                    let bound = variables
+                        .iter()
+                        .any(|v| v.split('.').next() == Some(field.name()));
+
+                    if bound || field.name() == self.body || !schema::is_scalar(field) {
+                        continue;
+                    }
+
+                    parameters.push(json!({
+                        "name": json::json_name(field),
+                        "in": "query",
+                        "schema": schema::field_schema(pool, field),
+                    }));
+                }
+            }
+        }
+
+        let response = if self.response_body.is_empty() {
+            schema::message_schema(&self.output_type)
+        } else {
+            schema::field_path_schema(pool, &self.output_type, &self.response_body)
+        };
+
+        let mut operation = json!({
+            "tags": [self.grpc_path.split('/').nth(1).unwrap_or_default()],
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": response } },
+                },
+                "default": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" },
+                        },
+                    },
+                },
+            },
+        });
+
+        if !self.body.is_empty() {
+            let body = if self.body == "*" {
+                schema::message_schema(&self.input_type)
+            } else {
+                schema::field_path_schema(pool, &self.input_type, &self.body)
+            };
+
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body } },
+            });
+        }
+
+        operation
+    }
+
+    /// Builds the JSON form of the RPC request from the HTTP request body,
+    /// its path variables and query parameters.
+    fn request(
+        &self,
+        body: &str,
+        captures: Vec<(String, String)>,
+        query: Vec<(String, String)>,
+    ) -> std::result::Result<Value, String> {
+        let mut request = Value::Object(Map::new());
+
+        if !self.body.is_empty() && !body.trim().is_empty() {
+            let body: Value =
+                serde_json::from_str(body).map_err(|e| format!("invalid JSON body: {}", e))?;
+
+            if self.body == "*" {
+                request = body;
+            } else {
+                set_field(&mut request, &self.body, body, false)?;
+            }
+        }
+
+        for (field, value) in captures {
+            set_field(&mut request, &field, Value::String(value), false)?;
+        }
+
+        // Query parameters are only used for fields not bound by the body.
+        if self.body != "*" {
+            for (field, value) in query {
+                set_field(&mut request, &field, Value::String(value), true)?;
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+/// Sets a field of a JSON object through its dotted path. Repeated values
+/// are collected into an array when `append` is set.
+fn set_field(
+    object: &mut Value,
+    path: &str,
+    value: Value,
+    append: bool,
+) -> std::result::Result<(), String> {
+    let mut current = object;
+    let mut fields = path.split('.').peekable();
+
+    while let Some(field) = fields.next() {
+        let map = match current {
+            Value::Object(map) => map,
+            _ => return Err(format!("field '{}' is not a message", path)),
+        };
+
+        if fields.peek().is_none() {
+            match map.get_mut(field) {
+                Some(Value::Array(items)) if append => items.push(value),
+                Some(existing) if append => {
+                    let first = existing.take();
+                    *existing = Value::Array(vec![first, value]);
+                }
+                _ => {
+                    map.insert(field.to_string(), value);
+                }
+            }
+
+            return Ok(());
+        }
+
+        current = map
+            .entry(field.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    Ok(())
+}
+
+/// Only the authorization and custom headers are forwarded to the RPC call.
+fn forwarded_headers(request: &Request<'_>) -> http::HeaderMap {
+    let mut headers = http::HeaderMap::new();
+
+    for header in request.headers().iter() {
+        let name = header.name().as_str().to_ascii_lowercase();
+        if name != "authorization" && !name.starts_with("x-") {
+            continue;
+        }
+
+        if let (Ok(name), Ok(value)) = (
+            http::header::HeaderName::from_bytes(name.as_bytes()),
+            http::header::HeaderValue::from_str(header.value()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+
+    headers
+}
+
+#[rocket::async_trait]
+impl Handler for Gateway {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let path: Vec<&str> = request.uri().path().segments().collect();
+        let matched = self.inner.bindings.iter().find_map(|binding| {
+            if binding.method != request.method() {
+                return None;
+            }
+
+            binding
+                .template
+                .matches(&path)
+                .map(|captures| (binding, captures))
+        });
+
+        let (binding, captures) = match matched {
+            Some(m) => m,
+            None => return Outcome::forward(data),
+        };
+
+        let query = request
+            .uri()
+            .query()
+            .map(|q| {
+                q.segments()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let body = if binding.body.is_empty() {
+            String::new()
+        } else {
+            let limit = request
+                .limits()
+                .get("json")
+                .unwrap_or(rocket::data::Limits::JSON);
+
+            match data.open(limit).into_string().await {
+                Ok(body) if body.is_complete() => body.into_inner(),
+                Ok(_) => {
+                    let status = tonic::Status::resource_exhausted("request body is too large");
+                    return Outcome::from(
+                        request,
+                        (
+                            Status::PayloadTooLarge,
+                            (ContentType::JSON, error_body(&status)),
+                        ),
+                    );
+                }
+                Err(e) => {
+                    let status = tonic::Status::invalid_argument(e.to_string());
+                    return Outcome::from(
+                        request,
+                        (Status::BadRequest, (ContentType::JSON, error_body(&status))),
+                    );
+                }
+            }
+        };
+
+        let response = match binding.request(&body, captures, query) {
+            Ok(r) => self.transcode(binding, r, forwarded_headers(request)).await,
+            Err(e) => {
+                let status = tonic::Status::invalid_argument(e);
+                (Status::BadRequest, error_body(&status))
+            }
+        };
+
+        Outcome::from(request, (response.0, (ContentType::JSON, response.1)))
+    }
+}
+
+impl From<Gateway> for Vec<Route> {
+    fn from(gateway: Gateway) -> Self {
+        let mut methods: Vec<rocket::http::Method> = Vec::new();
+        for binding in &gateway.inner.bindings {
+            if !methods.contains(&binding.method) {
+                methods.push(binding.method);
+            }
+        }
+
+        methods
+            .into_iter()
+            .map(|method| Route::ranked(GATEWAY_RANK, method, "/<path..>", gateway.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn binding(body: &str) -> Binding {
+        Binding {
+            method: rocket::http::Method::Patch,
+            template: PathTemplate::parse("/v1/examples/{example.id}").unwrap(),
+            body: body.to_string(),
+            response_body: String::new(),
+            grpc_path: "/example.ExampleService/UpdateExample".to_string(),
+            input_type: ".example.UpdateExampleRequest".to_string(),
+            output_type: ".example.Example".to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_binding_request() {
+        let captures = vec![("example.id".to_string(), "ex_1".to_string())];
+        let query = vec![
+            ("update_mask".to_string(), "name".to_string()),
+            ("tags".to_string(), "a".to_string()),
+            ("tags".to_string(), "b".to_string()),
+        ];
+
+        let request = binding("example")
+            .request(r#"{"name": "new"}"#, captures.clone(), query.clone())
+            .unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "example": {"name": "new", "id": "ex_1"},
+                "update_mask": "name",
+                "tags": ["a", "b"],
+            })
+        );
+
+        let request = binding("*")
+            .request(r#"{"example": {"name": "new"}}"#, captures.clone(), query)
+            .unwrap();
+        assert_eq!(request, json!({"example": {"name": "new", "id": "ex_1"}}));
+
+        assert!(binding("*").request("{", captures, vec![]).is_err());
+    }
+}
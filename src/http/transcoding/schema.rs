@@ -0,0 +1,177 @@
+// OpenAPI schemas for the messages and enums of a descriptor pool, following
+// the proto3 JSON mapping used by the gateway.
+
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto};
+use serde_json::{json, Map, Value};
+
+use crate::http::transcoding::descriptor::DescriptorPool;
+use crate::http::transcoding::json::{self, DURATION, EMPTY, FIELD_MASK, TIMESTAMP, WRAPPERS};
+
+/// Gives back the `$ref` for a message or enum type name.
+pub(crate) fn reference(type_name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", type_name.trim_start_matches('.')) })
+}
+
+/// Builds the component schemas of every message and enum of the pool.
+pub(crate) fn schemas(pool: &DescriptorPool) -> Map<String, Value> {
+    let mut schemas = Map::new();
+
+    for (name, message) in &pool.messages {
+        if is_map_entry(message) || is_well_known(name) {
+            continue;
+        }
+
+        let mut properties = Map::new();
+        for field in &message.field {
+            properties.insert(json::json_name(field), field_schema(pool, field));
+        }
+
+        schemas.insert(
+            name.trim_start_matches('.').to_string(),
+            json!({ "type": "object", "properties": properties }),
+        );
+    }
+
+    for (name, e) in &pool.enums {
+        let values: Vec<&str> = e.value.iter().map(|v| v.name()).collect();
+        schemas.insert(
+            name.trim_start_matches('.').to_string(),
+            json!({ "type": "string", "enum": values }),
+        );
+    }
+
+    schemas
+}
+
+/// Gives back the schema of a message type, which is inlined for well known
+/// types.
+pub(crate) fn message_schema(type_name: &str) -> Value {
+    match type_name {
+        TIMESTAMP => json!({ "type": "string", "format": "date-time" }),
+        DURATION => json!({ "type": "string", "example": "1.5s" }),
+        FIELD_MASK => json!({ "type": "string", "example": "name,updateTime" }),
+        EMPTY => json!({ "type": "object" }),
+        ".google.protobuf.Struct" | ".google.protobuf.Any" => json!({ "type": "object" }),
+        ".google.protobuf.Value" => json!({}),
+        ".google.protobuf.ListValue" => json!({ "type": "array", "items": {} }),
+        t if WRAPPERS.contains(&t) => {
+            let mut schema = wrapper_schema(t);
+            schema["nullable"] = Value::Bool(true);
+            schema
+        }
+        t => reference(t),
+    }
+}
+
+/// Gives back the schema of a (possibly nested) field of a message type.
+pub(crate) fn field_path_schema(pool: &DescriptorPool, type_name: &str, path: &str) -> Value {
+    let mut current = type_name.to_string();
+    let mut schema = json!({ "type": "string" });
+
+    for name in path.split('.') {
+        let field = pool
+            .messages
+            .get(&current)
+            .and_then(|m| m.field.iter().find(|f| f.name() == name));
+
+        match field {
+            Some(field) => {
+                schema = field_schema(pool, field);
+                current = field.type_name().to_string();
+            }
+            None => return json!({ "type": "string" }),
+        }
+    }
+
+    schema
+}
+
+/// Tells if a field can be given as a query parameter.
+pub(crate) fn is_scalar(field: &FieldDescriptorProto) -> bool {
+    match field.r#type() {
+        Type::Message => {
+            let t = field.type_name();
+            [TIMESTAMP, DURATION, FIELD_MASK].contains(&t) || WRAPPERS.contains(&t)
+        }
+        Type::Group => false,
+        _ => true,
+    }
+}
+
+pub(crate) fn field_schema(pool: &DescriptorPool, field: &FieldDescriptorProto) -> Value {
+    let item = match field.r#type() {
+        Type::Message => {
+            if let Some(entry) = pool
+                .messages
+                .get(field.type_name())
+                .filter(|m| is_map_entry(m))
+            {
+                let value = entry
+                    .field
+                    .iter()
+                    .find(|f| f.number() == 2)
+                    .map(|f| field_schema(pool, f))
+                    .unwrap_or_else(|| json!({}));
+
+                return json!({ "type": "object", "additionalProperties": value });
+            }
+
+            message_schema(field.type_name())
+        }
+        Type::Enum => reference(field.type_name()),
+        kind => scalar_schema(kind),
+    };
+
+    if field.label() == Label::Repeated {
+        json!({ "type": "array", "items": item })
+    } else {
+        item
+    }
+}
+
+fn scalar_schema(kind: Type) -> Value {
+    match kind {
+        Type::Double => json!({ "type": "number", "format": "double" }),
+        Type::Float => json!({ "type": "number", "format": "float" }),
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+            json!({ "type": "string", "format": "int64" })
+        }
+        Type::Uint64 | Type::Fixed64 => json!({ "type": "string", "format": "uint64" }),
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 => {
+            json!({ "type": "integer", "format": "int32" })
+        }
+        Type::Uint32 | Type::Fixed32 => json!({ "type": "integer", "format": "uint32" }),
+        Type::Bool => json!({ "type": "boolean" }),
+        Type::Bytes => json!({ "type": "string", "format": "byte" }),
+        _ => json!({ "type": "string" }),
+    }
+}
+
+fn wrapper_schema(type_name: &str) -> Value {
+    let kind = match type_name.trim_start_matches(".google.protobuf.") {
+        "DoubleValue" => Type::Double,
+        "FloatValue" => Type::Float,
+        "Int64Value" => Type::Int64,
+        "UInt64Value" => Type::Uint64,
+        "Int32Value" => Type::Int32,
+        "UInt32Value" => Type::Uint32,
+        "BoolValue" => Type::Bool,
+        "BytesValue" => Type::Bytes,
+        _ => Type::String,
+    };
+
+    scalar_schema(kind)
+}
+
+fn is_map_entry(message: &DescriptorProto) -> bool {
+    message
+        .options
+        .as_ref()
+        .map(|o| o.map_entry())
+        .unwrap_or(false)
+}
+
+fn is_well_known(type_name: &str) -> bool {
+    type_name.starts_with(".google.protobuf.")
+}
@@ -0,0 +1,237 @@
+// google.api.http path templates, e.g. `/v1/{name=shelves/*/books/*}:get`.
+//
+//   Template = "/" Segments [ Verb ] ;
+//   Segments = Segment { "/" Segment } ;
+//   Segment  = "*" | "**" | LITERAL | Variable ;
+//   Variable = "{" FieldPath [ "=" Segments ] "}" ;
+//   Verb     = ":" LITERAL ;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Matcher {
+    Literal(String),
+    Single,
+    Multi,
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    matcher: Matcher,
+    variable: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PathTemplate {
+    segments: Vec<Segment>,
+    verb: Option<String>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let path = match template.strip_prefix('/') {
+            Some(p) => p,
+            None => return Err(format!("template '{}' must start with '/'", template)),
+        };
+
+        let mut depth = 0;
+        let mut parts = Vec::new();
+        let mut verb = None;
+        let mut start = 0;
+
+        for (i, c) in path.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '/' if depth == 0 => {
+                    parts.push(&path[start..i]);
+                    start = i + 1;
+                }
+                ':' if depth == 0 => {
+                    verb = Some(path[i + 1..].to_string());
+                    parts.push(&path[start..i]);
+                    start = path.len() + 1;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if start <= path.len() {
+            parts.push(&path[start..]);
+        }
+
+        let mut segments = Vec::new();
+        for part in parts {
+            if let Some(variable) = part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                let (field, pattern) = match variable.split_once('=') {
+                    Some((field, pattern)) => (field, pattern),
+                    None => (variable, "*"),
+                };
+
+                for p in pattern.split('/') {
+                    segments.push(Segment {
+                        matcher: Self::matcher(p, template)?,
+                        variable: Some(field.to_string()),
+                    });
+                }
+            } else {
+                segments.push(Segment {
+                    matcher: Self::matcher(part, template)?,
+                    variable: None,
+                });
+            }
+        }
+
+        Ok(PathTemplate { segments, verb })
+    }
+
+    fn matcher(segment: &str, template: &str) -> Result<Matcher, String> {
+        match segment {
+            "" => Err(format!("template '{}' has an empty segment", template)),
+            "*" => Ok(Matcher::Single),
+            "**" => Ok(Matcher::Multi),
+            s if s.contains(['{', '}', '=', '*']) => Err(format!(
+                "template '{}' has an invalid segment '{}'",
+                template, s
+            )),
+            s => Ok(Matcher::Literal(s.to_string())),
+        }
+    }
+
+    /// Gives back the template as an OpenAPI path, like `/v1/{name}:get`.
+    pub fn openapi_path(&self) -> String {
+        let mut path = String::new();
+        let mut previous: Option<&String> = None;
+
+        for segment in &self.segments {
+            match &segment.variable {
+                Some(v) if previous == Some(v) => continue,
+                Some(v) => path.push_str(&format!("/{{{}}}", v)),
+                None => match &segment.matcher {
+                    Matcher::Literal(l) => path.push_str(&format!("/{}", l)),
+                    Matcher::Single => path.push_str("/*"),
+                    Matcher::Multi => path.push_str("/**"),
+                },
+            }
+
+            previous = segment.variable.as_ref();
+        }
+
+        if let Some(verb) = &self.verb {
+            path.push_str(&format!(":{}", verb));
+        }
+
+        path
+    }
+
+    /// Gives back the field paths of the template variables.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables: Vec<&str> = Vec::new();
+        for segment in &self.segments {
+            if let Some(v) = &segment.variable {
+                if variables.last() != Some(&v.as_str()) {
+                    variables.push(v);
+                }
+            }
+        }
+
+        variables
+    }
+
+    /// Matches the decoded segments of a request path, giving back the
+    /// captured variables with their field paths.
+    pub fn matches(&self, path: &[&str]) -> Option<Vec<(String, String)>> {
+        let mut path: Vec<&str> = path.to_vec();
+
+        if let Some(verb) = &self.verb {
+            let last = path.pop()?;
+            let rest = last.strip_suffix(verb.as_str())?.strip_suffix(':')?;
+            path.push(rest);
+        }
+
+        let mut captures: Vec<(String, Vec<&str>)> = Vec::new();
+        let mut j = 0;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let matched: Vec<&str> = match &segment.matcher {
+                Matcher::Multi => {
+                    let remaining = self.segments.len() - i - 1;
+                    let end = path.len().checked_sub(remaining)?;
+                    if end < j {
+                        return None;
+                    }
+                    path[j..end].to_vec()
+                }
+                Matcher::Single => vec![*path.get(j)?],
+                Matcher::Literal(l) if path.get(j)? == l => vec![*path.get(j)?],
+                Matcher::Literal(_) => return None,
+            };
+
+            j += matched.len();
+
+            if let Some(variable) = &segment.variable {
+                match captures.last_mut() {
+                    Some((name, values)) if name == variable => values.extend(matched),
+                    _ => captures.push((variable.clone(), matched)),
+                }
+            }
+        }
+
+        if j != path.len() {
+            return None;
+        }
+
+        Some(
+            captures
+                .into_iter()
+                .map(|(name, values)| (name, values.join("/")))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    pub fn test_path_template() {
+        let t = PathTemplate::parse("/v1/examples/{id}").unwrap();
+        assert_eq!(
+            t.matches(&["v1", "examples", "ex_1"]),
+            Some(vec![capture("id", "ex_1")])
+        );
+        assert_eq!(t.matches(&["v1", "examples"]), None);
+        assert_eq!(t.matches(&["v1", "examples", "ex_1", "x"]), None);
+
+        let t = PathTemplate::parse("/v1/{name=shelves/*/books/*}").unwrap();
+        assert_eq!(
+            t.matches(&["v1", "shelves", "s1", "books", "b1"]),
+            Some(vec![capture("name", "shelves/s1/books/b1")])
+        );
+        assert_eq!(t.matches(&["v1", "shelves", "s1", "notes", "b1"]), None);
+
+        let t = PathTemplate::parse("/v1/{example.id}:publish").unwrap();
+        assert_eq!(
+            t.matches(&["v1", "ex_1:publish"]),
+            Some(vec![capture("example.id", "ex_1")])
+        );
+        assert_eq!(t.matches(&["v1", "ex_1"]), None);
+
+        let t = PathTemplate::parse("/files/{path=**}").unwrap();
+        assert_eq!(
+            t.matches(&["files", "a", "b", "c.txt"]),
+            Some(vec![capture("path", "a/b/c.txt")])
+        );
+
+        let t = PathTemplate::parse("/v1/{name=shelves/*/books/*}:get").unwrap();
+        assert_eq!(t.openapi_path(), "/v1/{name}:get");
+        assert_eq!(t.variables(), vec!["name"]);
+
+        assert!(PathTemplate::parse("v1/examples").is_err());
+        assert!(PathTemplate::parse("/v1//examples").is_err());
+    }
+}
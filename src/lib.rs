@@ -1,12 +1,20 @@
+#[cfg(feature = "database")]
 extern crate mongodb;
+#[cfg(feature = "database")]
 pub use mongodb::bson::{doc, Document};
 
+pub mod check;
+pub mod config;
+#[cfg(feature = "database")]
 pub mod database;
 pub mod error;
 pub mod extensions;
+pub mod flags;
 pub mod grpc;
 pub mod http;
 pub mod service;
 
-mod config;
 mod definition;
+
+#[cfg(test)]
+mod testing;
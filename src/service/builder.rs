@@ -1,42 +1,262 @@
-use crate::database::{Credentials, Info};
+use crate::config::secrets::SecretProvider;
+#[cfg(feature = "database")]
+use crate::database::{ConnectionOptions, Credentials, Database, Info};
+use crate::definition::ServiceDefinition;
 use crate::error::Result;
+use crate::grpc::tls::TlsOptions;
 use crate::service::Service;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub(crate) const SERVICE_PORT: i64 = 9090;
 
 pub struct ServiceBuilder {
-    pub(crate) port: i64,
-    pub(crate) credentials: Credentials,
-    pub(crate) db_info: Info,
+    pub(crate) port: Option<i64>,
+    pub(crate) address: Option<String>,
+    pub(crate) dual_stack: Option<bool>,
+    pub(crate) grpc_web: Option<bool>,
+    pub(crate) grpc_tls: TlsOptions,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) method_timeouts: HashMap<String, Duration>,
+    pub(crate) config_file: Option<PathBuf>,
+    pub(crate) config_dir: Option<PathBuf>,
+    pub(crate) config_reload: Option<Duration>,
+    pub(crate) settings_file: Option<PathBuf>,
+    pub(crate) profile: Option<String>,
+    pub(crate) secret_providers: Vec<Arc<dyn SecretProvider>>,
+    pub(crate) secrets_refresh: Option<Duration>,
+
+    #[cfg(feature = "database")]
+    pub(crate) database: Option<bool>,
+    #[cfg(feature = "database")]
+    pub(crate) credentials: Option<Credentials>,
+    #[cfg(feature = "database")]
+    pub(crate) db_info: Option<Info>,
+    #[cfg(feature = "database")]
+    pub(crate) db_options: Option<ConnectionOptions>,
 }
 
 impl ServiceBuilder {
     fn new() -> Self {
         ServiceBuilder {
-            port: SERVICE_PORT,
-            credentials: Credentials::default(),
-            db_info: Info::default(),
+            port: None,
+            address: None,
+            dual_stack: None,
+            grpc_web: None,
+            grpc_tls: TlsOptions::default(),
+            timeout: None,
+            method_timeouts: HashMap::new(),
+            config_file: None,
+            config_dir: None,
+            config_reload: None,
+            settings_file: None,
+            profile: None,
+            secret_providers: Vec::new(),
+            secrets_refresh: None,
+            #[cfg(feature = "database")]
+            database: None,
+            #[cfg(feature = "database")]
+            credentials: None,
+            #[cfg(feature = "database")]
+            db_info: None,
+            #[cfg(feature = "database")]
+            db_options: None,
         }
     }
 
+    /// Sets the port the service listens at, from 0, which picks an
+    /// ephemeral one, to 65535. The SERVICE_PORT environment variable takes
+    /// precedence.
     pub fn with_port(&mut self, port: i64) -> &mut Self {
-        self.port = port;
+        self.port = Some(port);
+        self
+    }
+
+    /// Sets the address the service listens at, an IPv4 or IPv6 address,
+    /// like `0.0.0.0` or `::`, or a Unix domain socket path, like
+    /// `unix:/run/examples.sock`. The SERVICE_ADDRESS environment variable
+    /// takes precedence.
+    pub fn with_address(&mut self, address: &str) -> &mut Self {
+        self.address = Some(address.to_string());
+        self
+    }
+
+    /// Accepts IPv4 connections at an IPv6 address too. The
+    /// SERVICE_DUAL_STACK environment variable takes precedence.
+    pub fn with_dual_stack(&mut self, enabled: bool) -> &mut Self {
+        self.dual_stack = Some(enabled);
+        self
+    }
+
+    /// Enables or disables gRPC-Web (and HTTP/1.1) requests, overriding the
+    /// `[grpc]` section of the settings file.
+    pub fn with_grpc_web(&mut self, enabled: bool) -> &mut Self {
+        self.grpc_web = Some(enabled);
+        self
+    }
+
+    /// Serves gRPC requests over TLS, with a certificate and a key which are
+    /// PEM files or secret references. The GRPC_TLS_CERT and GRPC_TLS_KEY
+    /// environment variables take precedence.
+    pub fn with_grpc_tls(&mut self, cert: &str, key: &str) -> &mut Self {
+        self.grpc_tls.cert = Some(cert.to_string());
+        self.grpc_tls.key = Some(key.to_string());
+        self
+    }
+
+    /// Requires clients to present a certificate signed by a CA (mutual
+    /// TLS), a PEM file or a secret reference. The GRPC_TLS_CLIENT_CA
+    /// environment variable takes precedence.
+    pub fn with_grpc_client_ca(&mut self, ca: &str) -> &mut Self {
+        self.grpc_tls.client_ca = Some(ca.to_string());
+        self
+    }
+
+    /// Loads the TLS certificates again periodically, so they can be
+    /// replaced without restarting the service. The GRPC_TLS_RELOAD_INTERVAL
+    /// environment variable, in seconds, takes precedence.
+    pub fn with_grpc_tls_reload(&mut self, interval: Duration) -> &mut Self {
+        self.grpc_tls.reload = Some(interval);
+        self
+    }
+
+    /// Sets the request timeout of gRPC services. The SERVICE_TIMEOUT
+    /// environment variable, in seconds, takes precedence.
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the request timeout of a gRPC method, like
+    /// `examples.Examples/Search`, or of all methods of a service, like
+    /// `examples.Examples`, overriding the `[server.timeouts]` section of the
+    /// settings file.
+    pub fn with_method_timeout(&mut self, method: &str, timeout: Duration) -> &mut Self {
+        self.method_timeouts.insert(method.to_string(), timeout);
+        self
+    }
+
+    /// Sets the settings file, instead of `service.toml` from the current
+    /// directory. The SERVICE_SETTINGS_FILE environment variable takes
+    /// precedence.
+    pub fn with_settings_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.settings_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Selects a settings profile, like `prod`, whose file (`service.prod.toml`)
+    /// is merged over the settings file. The SERVICE_PROFILE environment
+    /// variable takes precedence.
+    pub fn with_profile(&mut self, profile: &str) -> &mut Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    /// Sets a file overlaying the `[config]` section of the settings file.
+    /// The SERVICE_CONFIG_FILE environment variable takes precedence.
+    pub fn with_config_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Registers a provider for secret references of its scheme, replacing
+    /// any other using the same one.
+    pub fn with_secret_provider<P: SecretProvider + 'static>(&mut self, provider: P) -> &mut Self {
+        self.secret_providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Refreshes the secrets periodically, calling their rotation callbacks
+    /// when they change. The SECRETS_REFRESH_INTERVAL environment variable,
+    /// in seconds, takes precedence.
+    pub fn with_secrets_refresh(&mut self, interval: Duration) -> &mut Self {
+        self.secrets_refresh = Some(interval);
+        self
+    }
+
+    /// Sets a directory whose `.toml` files overlay the `[config]` section of
+    /// the settings file. The SERVICE_CONFIG_DIR environment variable takes
+    /// precedence.
+    pub fn with_config_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Watches the settings files, checking them for changes periodically,
+    /// and reloads the typed settings when they change. The
+    /// CONFIG_RELOAD_INTERVAL environment variable, in seconds, takes
+    /// precedence.
+    pub fn with_config_reload(&mut self, interval: Duration) -> &mut Self {
+        self.config_reload = Some(interval);
+        self
+    }
+
+    /// Enables or disables the service database, overriding the `[database]`
+    /// section of the settings file.
+    #[cfg(feature = "database")]
+    pub fn with_database(&mut self, enabled: bool) -> &mut Self {
+        self.database = Some(enabled);
         self
     }
 
+    #[cfg(feature = "database")]
     pub fn with_database_info(&mut self, info: &Info) -> &mut Self {
-        self.db_info = info.clone();
+        self.db_info = Some(info.clone());
         self
     }
 
+    #[cfg(feature = "database")]
     pub fn with_database_credentials(&mut self, credentials: &Credentials) -> &mut Self {
-        self.credentials = credentials.clone();
+        self.credentials = Some(credentials.clone());
+        self
+    }
+
+    #[cfg(feature = "database")]
+    pub fn with_database_options(&mut self, options: &ConnectionOptions) -> &mut Self {
+        self.db_options = Some(options.clone());
         self
     }
 
     pub async fn build(&mut self) -> Result<Arc<Service>> {
-        Service::new(self).await
+        let definition =
+            ServiceDefinition::new(self.settings_file.as_deref(), self.profile.as_deref())?;
+
+        #[cfg(feature = "database")]
+        if self.database_enabled(&definition) {
+            let (credentials, info, _) = self.database_settings(&definition);
+            Database::validate(&credentials, &info)?;
+        }
+
+        Service::new(self, &definition).await
+    }
+
+    /// Gives back the database settings, the ones of the builder or, when
+    /// not set, the ones of the `[database]` section of the settings file.
+    #[cfg(feature = "database")]
+    pub(crate) fn database_settings(
+        &self,
+        definition: &ServiceDefinition,
+    ) -> (Credentials, Info, ConnectionOptions) {
+        let database = &definition.database;
+
+        (
+            self.credentials
+                .clone()
+                .unwrap_or_else(|| Credentials::from_definition(database)),
+            self.db_info
+                .clone()
+                .unwrap_or_else(|| Info::from_definition(database)),
+            self.db_options
+                .clone()
+                .unwrap_or_else(|| ConnectionOptions::from_definition(database)),
+        )
+    }
+
+    #[cfg(feature = "database")]
+    pub(crate) fn database_enabled(&self, definition: &ServiceDefinition) -> bool {
+        self.database.unwrap_or(definition.database.enabled)
     }
 }
 
@@ -0,0 +1,354 @@
+// Where gRPC services listen: IPv4 or IPv6 addresses, IPv6 ones accepting
+// IPv4 connections too when dual-stack, or Unix domain sockets, for sidecar
+// setups.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use futures::Stream;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{vars, Config, GetEnv};
+use crate::definition::ServerDefinition;
+use crate::error::{Error, Result};
+use crate::service::builder::SERVICE_PORT;
+
+const UNIX_PREFIX: &str = "unix:";
+
+/// Pending connections of a listening socket.
+const BACKLOG: i32 = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BindAddress {
+    Tcp {
+        address: SocketAddr,
+        dual_stack: bool,
+    },
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl BindAddress {
+    /// Parses a bind address, an IP address, with or without brackets, like
+    /// `0.0.0.0` or `[::]`, or a Unix domain socket path, like
+    /// `unix:/run/examples.sock`. Dual-stack needs an IPv6 address.
+    pub fn new(address: &str, port: u16, dual_stack: bool) -> Result<Self> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(invalid(format!("address '{}' has no socket path", address)));
+            }
+
+            if !cfg!(unix) {
+                return Err(invalid(format!(
+                    "address '{}': Unix domain sockets are not supported",
+                    address
+                )));
+            }
+
+            return Ok(BindAddress::Unix(PathBuf::from(path)));
+        }
+
+        let ip = address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map_err(|_| {
+                invalid(format!(
+                    "address '{}' is not an IP address or a unix: path",
+                    address
+                ))
+            })?;
+
+        if dual_stack && ip.is_ipv4() {
+            return Err(invalid(format!(
+                "address '{}': dual-stack needs an IPv6 address, like '::'",
+                address
+            )));
+        }
+
+        Ok(BindAddress::Tcp {
+            address: SocketAddr::new(ip, port),
+            dual_stack,
+        })
+    }
+
+    /// Loads the bind address from the SERVICE_ADDRESS and SERVICE_DUAL_STACK
+    /// environment variables, the builder options or the `[server]` section
+    /// of the settings file, in this order. It listens at every IPv4 address
+    /// by default.
+    pub fn from_settings(
+        address: Option<&str>,
+        port: u16,
+        dual_stack: Option<bool>,
+        definition: &ServerDefinition,
+    ) -> Result<Self> {
+        let address: String = Config::get_os_env(
+            "SERVICE_ADDRESS",
+            address
+                .map(|a| a.to_string())
+                .or_else(|| definition.address.clone()),
+        )
+        .unwrap_or_else(|| Ipv4Addr::UNSPECIFIED.to_string());
+
+        let dual_stack = vars::SERVICE_DUAL_STACK
+            .get(dual_stack.or(definition.dual_stack))
+            .unwrap_or(false);
+
+        BindAddress::new(&address, port, dual_stack)
+    }
+
+    /// Gives back the IP address, none for Unix domain sockets.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            BindAddress::Tcp { address, .. } => Some(address.ip()),
+            BindAddress::Unix(_) => None,
+        }
+    }
+
+    pub async fn bind(&self) -> Result<Listener> {
+        match self {
+            BindAddress::Tcp {
+                address,
+                dual_stack,
+            } => {
+                let socket = Socket::new(
+                    Domain::for_address(*address),
+                    Type::STREAM,
+                    Some(Protocol::TCP),
+                )
+                .map_err(|e| bind_error(self, e))?;
+
+                if address.is_ipv6() {
+                    socket
+                        .set_only_v6(!dual_stack)
+                        .map_err(|e| bind_error(self, e))?;
+                }
+
+                #[cfg(unix)]
+                socket
+                    .set_reuse_address(true)
+                    .map_err(|e| bind_error(self, e))?;
+
+                socket
+                    .bind(&(*address).into())
+                    .and_then(|_| socket.listen(BACKLOG))
+                    .and_then(|_| socket.set_nonblocking(true))
+                    .map_err(|e| bind_error(self, e))?;
+
+                let listener =
+                    TcpListener::from_std(socket.into()).map_err(|e| bind_error(self, e))?;
+                Ok(Listener::Tcp(listener))
+            }
+
+            #[cfg(unix)]
+            BindAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                // Sockets left behind by a previous run are replaced, other
+                // files are not.
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path).map_err(|e| bind_error(self, e))?;
+                    }
+                }
+
+                let listener =
+                    tokio::net::UnixListener::bind(path).map_err(|e| bind_error(self, e))?;
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+
+            #[cfg(not(unix))]
+            BindAddress::Unix(_) => Err(invalid(format!("address '{}' is not supported", self))),
+        }
+    }
+}
+
+impl std::fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BindAddress::Tcp { address, .. } => write!(f, "{}", address),
+            BindAddress::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+impl Listener {
+    /// Gives back the address the listener is bound to, with the port
+    /// picked by the system when binding port 0.
+    pub fn local_address(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default(),
+
+            #[cfg(unix)]
+            Listener::Unix(_, path) => format!("{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Gives back the port from the SERVICE_PORT environment variable, the
+/// builder option or the `[server]` section of the settings file, in this
+/// order, failing when it is out of range.
+pub(crate) fn port(port: Option<i64>, definition: &ServerDefinition) -> Result<u16> {
+    let port = port
+        .or_else(|| definition.port.map(i64::from))
+        .unwrap_or(SERVICE_PORT)
+        .to_string();
+
+    let port: String = Config::get_os_env("SERVICE_PORT", Some(port)).unwrap_or_default();
+    port.parse::<u16>()
+        .map_err(|_| invalid(format!("port '{}' must be a number from 0 to 65535", port)))
+}
+
+/// Gives back the connections accepted by a TCP listener.
+pub(crate) fn tcp_incoming(
+    listener: TcpListener,
+) -> impl Stream<Item = std::io::Result<TcpStream>> {
+    futures::stream::unfold(listener, |listener| async move {
+        let connection = listener.accept().await.map(|(stream, _)| stream);
+        Some((connection, listener))
+    })
+}
+
+/// Gives back the connections accepted by a Unix domain socket listener.
+#[cfg(unix)]
+pub(crate) fn unix_incoming(
+    listener: tokio::net::UnixListener,
+) -> impl Stream<Item = std::io::Result<unix::UnixConnection>> {
+    futures::stream::unfold(listener, |listener| async move {
+        let connection = listener
+            .accept()
+            .await
+            .map(|(stream, _)| unix::UnixConnection(stream));
+
+        Some((connection, listener))
+    })
+}
+
+#[cfg(unix)]
+pub(crate) mod unix {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::UnixStream;
+    use tonic::transport::server::Connected;
+
+    /// A Unix domain socket connection, served by tonic.
+    #[derive(Debug)]
+    pub(crate) struct UnixConnection(pub UnixStream);
+
+    impl Connected for UnixConnection {
+        type ConnectInfo = ();
+
+        fn connect_info(&self) -> Self::ConnectInfo {}
+    }
+
+    impl AsyncRead for UnixConnection {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for UnixConnection {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+        }
+    }
+}
+
+fn bind_error(address: &BindAddress, error: std::io::Error) -> Error {
+    Error::InternalOS(format!("could not listen at {}: {}", address, error))
+}
+
+fn invalid(message: String) -> Error {
+    Error::ServerSettings(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_port() {
+        let mut definition = ServerDefinition::default();
+        assert_eq!(port(None, &definition).unwrap(), 9090);
+
+        definition.port = Some(8080);
+        assert_eq!(port(None, &definition).unwrap(), 8080);
+        assert_eq!(port(Some(0), &definition).unwrap(), 0);
+        assert!(port(Some(65536), &definition).is_err());
+        assert!(port(Some(-1), &definition).is_err());
+    }
+
+    #[test]
+    pub fn test_bind_address_new() {
+        assert_eq!(
+            BindAddress::new("[::]", 9090, true).unwrap().to_string(),
+            "[::]:9090"
+        );
+        assert_eq!(
+            BindAddress::new("127.0.0.1", 80, false).unwrap().ip(),
+            "127.0.0.1".parse().ok()
+        );
+        assert_eq!(
+            BindAddress::new("unix:/run/examples.sock", 9090, false).unwrap(),
+            BindAddress::Unix(PathBuf::from("/run/examples.sock"))
+        );
+
+        assert!(BindAddress::new("0.0.0.0", 9090, true).is_err());
+        assert!(BindAddress::new("localhost", 9090, false).is_err());
+        assert!(BindAddress::new("unix:", 9090, false).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_bind_address_bind() {
+        let address = BindAddress::new("127.0.0.1", 0, false).unwrap();
+        let listener = address.bind().await.unwrap();
+        let local: SocketAddr = listener.local_address().parse().unwrap();
+        assert_ne!(local.port(), 0);
+        assert!(TcpStream::connect(local).await.is_ok());
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("service.sock");
+            let address = BindAddress::Unix(path.clone());
+
+            // Binding again replaces the socket left behind.
+            drop(address.bind().await.unwrap());
+            let listener = address.bind().await.unwrap();
+            assert_eq!(listener.local_address(), address.to_string());
+            assert!(tokio::net::UnixStream::connect(&path).await.is_ok());
+
+            std::fs::remove_file(&path).unwrap();
+            std::fs::write(&path, "").unwrap();
+            assert!(address.bind().await.is_err());
+        }
+    }
+}
@@ -1,71 +1,344 @@
 pub mod builder;
+pub(crate) mod listener;
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use futures_util::FutureExt;
 use http::{request::Request, response::Response};
 use logger::{builder::LoggerBuilder, fields::FieldValue, Logger};
 use tokio::signal;
+use tokio::sync::watch;
 use tonic::body::BoxBody;
 use tonic::transport::{Body, NamedService};
+use tower::layer::util::{Identity, Stack};
 
-use crate::config::{Config, ConfigBuilder, GetEnv};
+use crate::config::reload::FileWatcher;
+use crate::config::{self, vars, Config, ConfigBuilder, GetEnv};
+#[cfg(feature = "database")]
 use crate::database;
-use crate::definition::{ServiceDefinition, ServiceKind};
-use crate::error::Result;
+use crate::definition::{ClientDefinition, LoggingDefinition, ServiceDefinition, ServiceKind};
+use crate::error::{Error, Result};
+use crate::flags::Flags;
 use crate::grpc;
+use crate::grpc::deadline::{DeadlineLayer, Timeouts};
+use crate::grpc::tls::{self, TlsAcceptor, TlsSettings};
+use crate::grpc::web::{GrpcWebLayer, GrpcWebSettings};
 use crate::http as microhttp;
+use crate::http::settings::HttpSettings;
 use crate::service::builder::ServiceBuilder;
+use crate::service::listener::{BindAddress, Listener};
+
+pub use crate::definition::LogLevel;
+
+pub(crate) type GrpcLayers =
+    Stack<DeadlineLayer, Stack<GrpcWebLayer, Stack<grpc::GrpcMiddleware, Identity>>>;
+
+/// Request timeout of gRPC services when the settings do not set one.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub struct Service {
     pub logger: Arc<Logger>,
     pub config: Config,
-    pub database: Arc<database::Database>,
+    #[cfg(feature = "database")]
+    pub database: Option<Arc<database::Database>>,
 
     name: String,
+    version: String,
+    profile: Option<String>,
 
     #[allow(dead_code)]
     kind: ServiceKind,
-    port: i64,
+    port: u16,
+    bind: BindAddress,
+    timeouts: Timeouts,
+    access_log: AtomicBool,
+    log_level: watch::Sender<LogLevel>,
+    log_level_watch: watch::Receiver<LogLevel>,
+    clients: HashMap<String, ClientDefinition>,
+    topics: Vec<String>,
+    subscriptions: Vec<String>,
+    http: HttpSettings,
+    grpc_web: Option<GrpcWebSettings>,
+    tls: Option<Arc<TlsAcceptor>>,
+    flags: RwLock<Arc<Flags>>,
 }
 
 impl Service {
-    async fn new(builder: &ServiceBuilder) -> Result<Arc<Self>> {
-        let definition = ServiceDefinition::new()?;
-        let logger = Arc::new(
-            LoggerBuilder::new()
-                .with_field(
-                    "service.name",
-                    FieldValue::String(definition.info.name.clone()),
-                )
-                .with_field(
-                    "service.version",
-                    FieldValue::String(definition.info.version.clone()),
-                )
-                .with_field(
-                    "service.type",
-                    FieldValue::String(definition.info.kind.to_string()),
-                )
-                .build(),
-        );
+    async fn new(builder: &ServiceBuilder, definition: &ServiceDefinition) -> Result<Arc<Self>> {
+        let mut logger = LoggerBuilder::new();
+        logger
+            .with_field(
+                "service.name",
+                FieldValue::String(definition.info.name.clone()),
+            )
+            .with_field(
+                "service.version",
+                FieldValue::String(definition.info.version.clone()),
+            )
+            .with_field(
+                "service.type",
+                FieldValue::String(definition.info.kind.to_string()),
+            );
+
+        let mut fields: Vec<_> = definition.logging.fields.iter().collect();
+        fields.sort();
+        for (name, value) in fields {
+            logger.with_field(name, FieldValue::String(value.clone()));
+        }
+
+        let logger = Arc::new(logger.build());
 
         logger.info("starting service");
 
-        Ok(Arc::new(Service {
+        let port = listener::port(builder.port, &definition.server)?;
+        let bind = BindAddress::from_settings(
+            builder.address.as_deref(),
+            port,
+            builder.dual_stack,
+            &definition.server,
+        )?;
+
+        // Timeouts of the environment and of the settings file are in
+        // seconds, the one of the builder keeps its precision.
+        let timeout = vars::SERVICE_TIMEOUT
+            .get(None)
+            .map(Duration::from_secs)
+            .or(builder.timeout)
+            .or_else(|| definition.server.timeout.map(Duration::from_secs))
+            .unwrap_or(DEFAULT_TIMEOUT);
+
+        // Method timeouts of the builder take precedence over the ones of
+        // the settings file, whether their names have a leading '/' or not.
+        let mut methods: HashMap<String, Duration> = HashMap::new();
+        let file = definition
+            .server
+            .timeouts
+            .iter()
+            .map(|(name, timeout)| (name, Duration::from_secs(*timeout)));
+        for (name, timeout) in file.chain(builder.method_timeouts.iter().map(|(n, t)| (n, *t))) {
+            methods.insert(name.trim_start_matches('/').to_string(), timeout);
+        }
+
+        // The HTTP settings are only loaded by HTTP services, others listen
+        // at the service address.
+        let kind = ServiceKind::from_str(&definition.info.kind);
+        let mut http = match kind {
+            ServiceKind::Http => HttpSettings::new(&definition.http)?,
+            _ => HttpSettings::default(),
+        };
+        if http.address.is_none() {
+            http.address = bind.ip();
+        }
+        if http.address.is_none() && kind == ServiceKind::Http {
+            return Err(Error::ServerSettings(format!(
+                "address '{}': HTTP services need an IP address",
+                bind
+            )));
+        }
+        let grpc_web = GrpcWebSettings::new(builder.grpc_web, &definition.grpc)?;
+        let tls = TlsSettings::new(&builder.grpc_tls, &definition.grpc)?;
+        let flags = Flags::new(&definition.flags)?;
+        let config_file: Option<PathBuf> =
+            Config::get_os_env(config::CONFIG_FILE_ENV, builder.config_file.clone());
+        let config_dir: Option<PathBuf> =
+            Config::get_os_env(config::CONFIG_DIR_ENV, builder.config_dir.clone());
+        let config = ConfigBuilder::new()
+            .with_logger(&logger)
+            .with_section(definition.config.as_ref())
+            .with_dir(config_dir.as_deref())
+            .with_file(config_file.as_deref())
+            .with_secret_providers(&builder.secret_providers)
+            .build()?;
+
+        // The environment interval is in seconds, the one of the builder
+        // keeps its precision.
+        let refresh = vars::SECRETS_REFRESH_INTERVAL
+            .get(None)
+            .map(Duration::from_secs)
+            .or(builder.secrets_refresh);
+        if let Some(interval) = refresh.filter(|i| !i.is_zero()) {
+            Service::refresh_secrets(&config, &logger, interval);
+        }
+
+        #[cfg(feature = "database")]
+        let database = if builder.database_enabled(definition) {
+            let (credentials, info, options) = builder.database_settings(definition);
+
+            Some(
+                database::Database::new(&credentials, &info, &options, config.secrets(), &logger)
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let tls = match tls {
+            Some(tls) => Some(Arc::new(
+                TlsAcceptor::new(&tls, grpc_web.is_some(), config.secrets()).await?,
+            )),
+            None => None,
+        };
+
+        let (log_level, log_level_watch) = watch::channel(definition.logging.level);
+        let service = Arc::new(Service {
             name: definition.info.name.clone(),
-            kind: ServiceKind::from_str(&definition.info.kind),
-            config: ConfigBuilder::new().with_logger(&logger).build(),
+            version: definition.info.version.clone(),
+            profile: definition.profile.clone(),
+            kind,
+            config,
             logger: logger.clone(),
-            port: Service::get_service_port(builder),
-            database: database::Database::new(&builder.credentials, &builder.db_info).await?,
-        }))
+            port,
+            bind,
+            timeouts: Timeouts::new(timeout, &methods),
+            access_log: AtomicBool::new(definition.logging.access_log),
+            log_level,
+            log_level_watch,
+            clients: definition.clients.clone(),
+            topics: definition.pubsub.topics.clone(),
+            subscriptions: definition.pubsub.subscriptions.clone(),
+            http,
+            grpc_web,
+            tls,
+            flags: RwLock::new(Arc::new(flags)),
+            #[cfg(feature = "database")]
+            database,
+        });
+
+        // The environment interval is in seconds, the one of the builder
+        // keeps its precision.
+        let reload = vars::CONFIG_RELOAD_INTERVAL
+            .get(None)
+            .map(Duration::from_secs)
+            .or(builder.config_reload);
+        if let Some(interval) = reload.filter(|i| !i.is_zero()) {
+            Service::reload_config(&service, definition, interval);
+        }
+
+        if let Some(interval) = service.tls.as_ref().and_then(|t| t.reload_interval()) {
+            Service::reload_tls(&service, interval);
+        }
+
+        Ok(service)
+    }
+
+    /// Checks the settings files for changes in background, until the
+    /// service is gone, reloading the typed settings, the feature flags and
+    /// the logging settings when they change. Invalid updates are logged and
+    /// ignored, as well as changes to the sections that need a restart.
+    fn reload_config(service: &Arc<Service>, definition: &ServiceDefinition, interval: Duration) {
+        let mut files = definition.files();
+        files.extend(service.config.files());
+
+        let mut watcher = FileWatcher::new(files);
+        let path = definition.path.clone();
+        let profile = definition.profile.clone();
+        let mut content = definition.content.clone();
+        let service = Arc::downgrade(service);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let service = match service.upgrade() {
+                    Some(service) => service,
+                    None => break,
+                };
+
+                if !watcher.changed() {
+                    continue;
+                }
+
+                let result =
+                    ServiceDefinition::new(Some(&path), profile.as_deref()).and_then(|d| {
+                        let flags = Flags::new(&d.flags)?;
+                        let reloaded = service.config.reload(d.config.as_ref())?;
+                        let logging = service.set_logging(&d.logging);
+                        Ok((service.set_flags(flags) || reloaded || logging, d))
+                    });
+
+                match result {
+                    Ok((reloaded, d)) => {
+                        let keys = d.restart_keys(&content);
+                        if !keys.is_empty() {
+                            service.logger.errorf(
+                                "service settings changed, the service must be restarted to use them",
+                                logger::fields!("config.keys" => FieldValue::String(keys.join(", "))),
+                            );
+                        }
+
+                        if reloaded {
+                            service.logger.info("service config reloaded");
+                        }
+
+                        content = d.content;
+                    }
+                    Err(e) => service.logger.errorf(
+                        "service config update rejected",
+                        logger::fields!("config.error" => FieldValue::String(e.to_string())),
+                    ),
+                }
+            }
+        });
     }
 
-    fn get_service_port(builder: &ServiceBuilder) -> i64 {
-        Config::get_os_env("SERVICE_PORT", Some(builder.port)).unwrap()
+    /// Loads the TLS certificates again in background, until the service is
+    /// gone. Invalid certificates are logged and ignored.
+    fn reload_tls(service: &Arc<Service>, interval: Duration) {
+        let service = Arc::downgrade(service);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let service = match service.upgrade() {
+                    Some(service) => service,
+                    None => break,
+                };
+
+                let tls = match &service.tls {
+                    Some(tls) => tls,
+                    None => break,
+                };
+
+                match tls.reload(service.config.secrets()).await {
+                    Ok(true) => service.logger.info("TLS certificates reloaded"),
+                    Ok(false) => {}
+                    Err(e) => service.logger.errorf(
+                        "TLS certificates update rejected",
+                        logger::fields!("tls.error" => FieldValue::String(e.to_string())),
+                    ),
+                }
+            }
+        });
+    }
+
+    /// Refreshes the secrets in background, until the service is gone.
+    fn refresh_secrets(config: &Config, logger: &Arc<Logger>, interval: Duration) {
+        let secrets = Arc::downgrade(config.secrets());
+        let logger = logger.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let secrets = match secrets.upgrade() {
+                    Some(secrets) => secrets,
+                    None => break,
+                };
+
+                if let Err(e) = secrets.refresh().await {
+                    logger.errorf(
+                        "could not refresh secrets",
+                        logger::fields!("secrets.error" => FieldValue::String(e.to_string())),
+                    );
+                }
+            }
+        });
     }
 
     /// Gives back the current service name.
@@ -73,11 +346,144 @@ impl Service {
         &self.name
     }
 
+    /// Gives back the current service version.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Gives back the feature flags of the service.
+    pub fn flags(&self) -> Arc<Flags> {
+        self.flags.read().unwrap().clone()
+    }
+
+    /// Replaces the feature flags, telling if they changed.
+    fn set_flags(&self, flags: Flags) -> bool {
+        let mut current = self.flags.write().unwrap();
+        if **current == flags {
+            return false;
+        }
+
+        *current = Arc::new(flags);
+        true
+    }
+
+    /// Gives back the log level of the `[logging]` section, updated when the
+    /// settings are reloaded. The logger itself has no levels, handlers can
+    /// use it to skip their less important messages.
+    pub fn log_level(&self) -> watch::Receiver<LogLevel> {
+        self.log_level_watch.clone()
+    }
+
+    /// Tells if every request of HTTP services is logged.
+    pub(crate) fn access_log(&self) -> bool {
+        self.access_log.load(Ordering::Relaxed) && *self.log_level_watch.borrow() <= LogLevel::Info
+    }
+
+    /// Replaces the logging settings, telling if they changed.
+    fn set_logging(&self, logging: &LoggingDefinition) -> bool {
+        let access_log = self.access_log.swap(logging.access_log, Ordering::Relaxed);
+        let level = *self.log_level_watch.borrow();
+        if level != logging.level {
+            let _ = self.log_level.send(logging.level);
+        }
+
+        access_log != logging.access_log || level != logging.level
+    }
+
+    /// Gives back the settings profile the service was started with.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Gives back the URL of another service: the CLIENT_<NAME>_ADDRESS
+    /// environment variable, its address in the `[clients]` section of the
+    /// settings file, or the default one given by `grpc::Client::url`.
+    pub fn client_url(&self, name: &str) -> String {
+        let address = self.clients.get(name).and_then(|c| c.address.clone());
+        let env: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        Config::get_os_env(&format!("CLIENT_{}_ADDRESS", env), address)
+            .unwrap_or_else(|| grpc::Client::url(name))
+    }
+
+    /// Gives back an endpoint to connect with another service, using the
+    /// timeouts of the `[clients]` section of the settings file.
+    ///
+    /// ```ignore
+    /// let channel = service.client_endpoint("payments")?.connect().await?;
+    /// let client = PaymentsServiceClient::new(channel);
+    /// ```
+    pub fn client_endpoint(&self, name: &str) -> Result<tonic::transport::Endpoint> {
+        let url = self.client_url(name);
+        let mut endpoint = match tonic::transport::Endpoint::from_shared(url.clone()) {
+            Ok(endpoint) => endpoint,
+            Err(_) => {
+                return Err(Error::UnsupportedSetting(format!(
+                    "clients.{}: address '{}' is invalid",
+                    name, url
+                )))
+            }
+        };
+
+        if let Some(client) = self.clients.get(name) {
+            if let Some(timeout) = client.timeout {
+                endpoint = endpoint.timeout(Duration::from_secs(timeout));
+            }
+
+            if let Some(timeout) = client.connect_timeout {
+                endpoint = endpoint.connect_timeout(Duration::from_secs(timeout));
+            }
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Gives back a channel with another service, like `client_endpoint`,
+    /// whose calls propagate the deadline of the request being handled. It
+    /// connects on its first call.
+    ///
+    /// ```ignore
+    /// let client = PaymentsServiceClient::new(service.client_channel("payments")?);
+    /// ```
+    pub fn client_channel(&self, name: &str) -> Result<grpc::DeadlineChannel> {
+        let endpoint = self.client_endpoint(name)?;
+        Ok(grpc::Client::with_deadline(endpoint.connect_lazy()))
+    }
+
+    /// Gives back the pubsub topics the service publishes to.
+    pub fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    /// Gives back the pubsub topics the service subscribes to.
+    pub fn subscriptions(&self) -> &[String] {
+        &self.subscriptions
+    }
+
     /// Retrieves the Service object from RPC's request argument.
     pub fn from_request<B: prost::Message>(request: &tonic::Request<B>) -> Arc<Service> {
         request.extensions().get::<Arc<Service>>().unwrap().clone()
     }
 
+    /// The layers every gRPC request goes through, whether it comes from a
+    /// client or from the HTTP transcoding gateway. They give RPC methods
+    /// access to the service and apply the request timeouts.
+    pub(crate) fn grpc_layers(service: &Arc<Service>) -> tower::ServiceBuilder<GrpcLayers> {
+        tower::ServiceBuilder::new()
+            .layer(grpc::GrpcMiddleware::new(service))
+            .layer(GrpcWebLayer::new(&service.grpc_web))
+            .layer(DeadlineLayer::new(&service.timeouts))
+    }
+
     /// Puts the service to run in the gRPC mode.
     pub async fn serve_as_grpc<S>(
         service: &Arc<Service>,
@@ -92,32 +498,65 @@ impl Service {
         S::Future: Send + 'static,
         S::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
     {
-        let layer = tower::ServiceBuilder::new()
-            .timeout(Duration::from_secs(30))
-            .layer(grpc::GrpcMiddleware::new(service))
-            .into_inner();
+        let layer = Service::grpc_layers(service).into_inner();
 
         let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let grpc_web = service.grpc_web.is_some();
 
-        let addr = SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-            service.port.try_into().unwrap(),
-        );
+        let listener = service.bind.bind().await?;
 
         service.logger.infof(
             "service is running",
             logger::fields! {
-                "service.address" => FieldValue::String(format!(":{}", service.port)),
+                "service.address" => FieldValue::String(listener.local_address()),
+                "service.tls" => FieldValue::String(service.tls.is_some().to_string()),
             },
         );
 
+        let tls = service.tls.clone();
+        let logger = service.logger.clone();
+
         let jh = tokio::spawn(async move {
-            tonic::transport::Server::builder()
+            let router = tonic::transport::Server::builder()
+                .accept_http1(grpc_web)
                 .layer(layer)
-                .add_service(grpc_server)
-                .serve_with_shutdown(addr, shutdown_rx.map(drop))
-                .await
-                .unwrap();
+                .add_service(grpc_server);
+
+            let shutdown = shutdown_rx.map(drop);
+            match (listener, tls) {
+                (Listener::Tcp(listener), None) => router
+                    .serve_with_incoming_shutdown(listener::tcp_incoming(listener), shutdown)
+                    .await
+                    .unwrap(),
+
+                (Listener::Tcp(listener), Some(tls)) => {
+                    let incoming = tls::incoming(&tls, listener::tcp_incoming(listener), &logger);
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown)
+                        .await
+                        .unwrap()
+                }
+
+                #[cfg(unix)]
+                (Listener::Unix(listener, path), tls) => {
+                    let incoming = listener::unix_incoming(listener);
+                    match tls {
+                        Some(tls) => router
+                            .serve_with_incoming_shutdown(
+                                tls::incoming(&tls, incoming, &logger),
+                                shutdown,
+                            )
+                            .await
+                            .unwrap(),
+                        None => router
+                            .serve_with_incoming_shutdown(incoming, shutdown)
+                            .await
+                            .unwrap(),
+                    }
+
+                    let _ = std::fs::remove_file(path);
+                }
+            }
         });
 
         tokio::spawn(async move {
@@ -137,36 +576,67 @@ impl Service {
         self.logger.info("stopping service");
     }
 
-    /// Give access to the service database driver.
-    pub fn database(&self) -> Arc<database::Database> {
-        self.database.clone()
+    /// Give access to the service database driver, connected when the
+    /// service started. It fails with a FailedPrecondition status when the
+    /// service runs without a database.
+    #[cfg(feature = "database")]
+    pub fn database(&self) -> database::DatabaseResult<Arc<database::Database>> {
+        match &self.database {
+            Some(db) => Ok(db.clone()),
+            None => Err(grpc::rpc::Error::new(
+                grpc::rpc::ErrorCode::Precondition,
+                Some("service has no database configured"),
+            )
+            .to_status()),
+        }
     }
 
-    /// Puts the service to run in the HTTP mode.
+    /// Puts the service to run in the HTTP mode. Besides giving handlers
+    /// access to the service, it assigns request ids, logs every request
+    /// through the service logger and answers internal errors (and panics)
+    /// with JSON error bodies.
     pub async fn serve_as_http(
         service: &Arc<Service>,
         http_server: rocket::Rocket<rocket::Build>,
     ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        microhttp::fairing::set_panic_hook();
+
+        // Internal errors are answered by the catchers of the service itself
+        // when it declares them.
+        let catchers = microhttp::fairing::catchers(&http_server);
+        // The access log is always attached, it can be turned on and off
+        // when the settings are reloaded.
+        let mut http_server = http_server
+            .manage(service.clone())
+            .attach(microhttp::fairing::RequestIdFairing)
+            .attach(microhttp::fairing::PanicFairing)
+            .attach(microhttp::fairing::AccessLog)
+            .register("/", catchers);
+
+        if let Some(cors) = &service.http.cors {
+            http_server = http_server.attach(microhttp::cors::Cors::new(cors));
+        }
+
+        let http_server = http_server.ignite().await?;
+        let config = http_server.config();
+
         service.logger.infof(
             "service is running",
             logger::fields! {
-                "service.address" => FieldValue::String(format!(":{}", service.port)),
+                "service.address" => FieldValue::String(
+                    std::net::SocketAddr::new(config.address, config.port).to_string()
+                ),
             },
         );
 
-        http_server
-            .manage(service.clone())
-            .ignite()
-            .await?
-            .launch()
-            .await?;
+        http_server.launch().await?;
 
         Ok(())
     }
 
     /// Builds and returns default settings for HTTP services.
     pub fn http_config(&self) -> figment::Figment {
-        microhttp::config(self.port, &self.name)
+        microhttp::config(self.port, &self.name, &self.http)
     }
 }
 
@@ -180,9 +650,69 @@ impl Drop for Service {
 mod tests {
     use super::*;
 
-    #[test]
-    pub fn test_service_new() {
-        let svc = ServiceBuilder::default().build();
-        svc.unwrap().start();
+    #[cfg(feature = "database")]
+    use crate::database::Info;
+
+    // The settings files live as long as the directory given back.
+    fn settings_file() -> (tempfile::TempDir, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        std::fs::write(
+            dir.join("service.toml"),
+            r#"
+            name = "example"
+            version = "0.1.0"
+            type = "grpc"
+        "#,
+        )
+        .unwrap();
+
+        std::fs::write(
+            dir.join("service.dev.toml"),
+            r#"
+            version = "0.1.0-dev"
+
+            [database]
+            enabled = false
+        "#,
+        )
+        .unwrap();
+
+        let path = dir.join("service.toml");
+        (tmp, path)
+    }
+
+    #[cfg(feature = "database")]
+    #[tokio::test]
+    pub async fn test_service_new() {
+        let _env = crate::testing::EnvGuard::new();
+        let (_dir, path) = settings_file();
+        let svc = ServiceBuilder::default()
+            .with_settings_file(path)
+            .with_database_info(&Info {
+                database_name: None,
+                collection: None,
+            })
+            .build()
+            .await;
+
+        assert!(matches!(svc, Err(Error::DatabaseSettings(_))));
+    }
+
+    #[tokio::test]
+    pub async fn test_service_new_with_profile() {
+        let _env = crate::testing::EnvGuard::new();
+        let (_dir, path) = settings_file();
+        let svc = ServiceBuilder::default()
+            .with_settings_file(path)
+            .with_profile("dev")
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(svc.name(), "example");
+        assert_eq!(svc.version(), "0.1.0-dev");
+        assert_eq!(svc.profile(), Some("dev"));
     }
 }
@@ -0,0 +1,46 @@
+// Helpers shared by the tests of the crate.
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Tests run in parallel in the same process, so the ones changing or
+/// reading the environment variables of others take turns.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Sets environment variables for a test, giving back their previous values
+/// when dropped, even when an assertion fails first. Only one test holds it
+/// at a time, tests that only read the variables set by others should hold
+/// one as well.
+pub(crate) struct EnvGuard {
+    previous: Vec<(String, Option<String>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl EnvGuard {
+    pub fn new() -> Self {
+        EnvGuard {
+            previous: Vec::new(),
+            _lock: ENV.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        if !self.previous.iter().any(|(k, _)| k == key) {
+            self.previous
+                .push((key.to_string(), std::env::var(key).ok()));
+        }
+
+        std::env::set_var(key, value);
+        self
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (key, value) in self.previous.drain(..).rev() {
+            match value {
+                Some(value) => std::env::set_var(&key, value),
+                None => std::env::remove_var(&key),
+            }
+        }
+    }
+}